@@ -0,0 +1,104 @@
+mod pattern;
+mod search;
+mod selfplay;
+mod train;
+
+pub use pattern::Weights;
+pub use search::{best_move, negamax, DiscCount, Evaluator};
+pub use selfplay::{self_play, self_play_game, PlayedGame, SelfPlayConfig};
+pub use train::{fit, label, td_learn, Label, Sample};
+
+use reversi_core::*;
+
+pub fn legal_moves(field: &Field, color: BorW) -> Vec<Position> {
+    let mut moves = Vec::new();
+    for x in 0..8 {
+        for y in 0..8 {
+            let p = Position::new(x, y).unwrap();
+            if check_putable(field, p, color) {
+                moves.push(p);
+            }
+        }
+    }
+    moves
+}
+
+pub fn play(field: &Field, p: Position, color: BorW) -> Field {
+    let mut next = field.clone();
+    next.set(p, Masu::Putted(color));
+    auto_reverse(&mut next, p, color);
+    next
+}
+
+pub fn empties(field: &Field) -> usize {
+    64 - field.count(BorW::Black) - field.count(BorW::White)
+}
+
+pub fn disc_diff(field: &Field, color: BorW) -> f32 {
+    field.count(color) as f32 - field.count(get_another_color(color)) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    #[test]
+    fn legal_moves_test() {
+        let field = Field::new();
+        assert!(legal_moves(&field, BorW::Black).len() == 4);
+        let next = play(&field, Position::new(4, 2).unwrap(), BorW::Black);
+        assert!(next.count(BorW::Black) == 4);
+        assert!(next.count(BorW::White) == 1);
+    }
+    #[test]
+    fn search_prefers_more_discs_test() {
+        let mut field = Field::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                field.set(Position::new(x, y).unwrap(), Masu::Putted(BorW::Black));
+            }
+        }
+        field.set(Position::new(0, 0).unwrap(), Masu::Empty);
+        field.set(Position::new(1, 0).unwrap(), Masu::Putted(BorW::White));
+        field.set(Position::new(7, 7).unwrap(), Masu::Empty);
+        assert!(
+            best_move(&field, BorW::Black, 2, &DiscCount) == Some(Position::new(0, 0).unwrap())
+        );
+        assert!(negamax(&field, BorW::Black, 10, &DiscCount) == 63.0);
+    }
+    #[test]
+    fn weights_roundtrip_test() {
+        let mut weights = Weights::new();
+        let field = Field::new();
+        weights.update(&field, BorW::Black, 0.5);
+        let mut buf = Vec::new();
+        weights.write_to(&mut buf).unwrap();
+        let loaded = Weights::read_from(&mut buf.as_slice()).unwrap();
+        assert!(loaded.evaluate(&field, BorW::Black) == weights.evaluate(&field, BorW::Black));
+        assert!(Weights::read_from(&mut &buf[1..]).is_err());
+    }
+    #[test]
+    fn symmetric_evaluation_test() {
+        let mut weights = Weights::new();
+        let field = play(&Field::new(), Position::new(4, 2).unwrap(), BorW::Black);
+        weights.update(&field, BorW::White, 1.0);
+        let mirrored = play(&Field::new(), Position::new(3, 5).unwrap(), BorW::Black);
+        let diff = weights.evaluate(&field, BorW::White) - weights.evaluate(&mirrored, BorW::White);
+        assert!(diff.abs() < 1e-4);
+    }
+    #[test]
+    fn training_reduces_error_test() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let config = SelfPlayConfig {
+            depth: 1,
+            epsilon: 0.5,
+        };
+        let mut weights = Weights::new();
+        let games = self_play(&weights, &config, 4, &mut rng);
+        let samples = label(&games, Label::Outcome, &weights);
+        let first = fit(&mut weights, &samples, 1, 0.01);
+        let last = fit(&mut weights, &samples, 5, 0.01);
+        assert!(last < first);
+        td_learn(&mut weights, &games, 0.01);
+    }
+}
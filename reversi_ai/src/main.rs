@@ -0,0 +1,141 @@
+use rand::SeedableRng;
+use reversi_ai::*;
+
+const USAGE: &str = "usage: reversi_train [options]
+  --out FILE          weight file to write (default: weights.bin)
+  --init FILE         weight file to start from
+  --iterations N      self-play / training rounds (default: 10)
+  --games N           self-play games per round (default: 100)
+  --depth N           search depth used during self-play (default: 1)
+  --epsilon P         probability of a random move in self-play (default: 0.1)
+  --label outcome|search:DEPTH
+                      how positions are labelled (default: outcome)
+  --exact N           empties solved exactly by search labels (default: 10)
+  --method regression|td
+                      how weights are fitted (default: regression)
+  --epochs N          regression epochs per round (default: 3)
+  --rate R            learning rate (default: 0.01)
+  --seed N            random seed (default: 0)";
+
+struct Options {
+    out: String,
+    init: Option<String>,
+    iterations: usize,
+    games: usize,
+    depth: u32,
+    epsilon: f64,
+    label: Label,
+    td: bool,
+    epochs: usize,
+    rate: f32,
+    seed: u64,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        out: "weights.bin".to_string(),
+        init: None,
+        iterations: 10,
+        games: 100,
+        depth: 1,
+        epsilon: 0.1,
+        label: Label::Outcome,
+        td: false,
+        epochs: 3,
+        rate: 0.01,
+        seed: 0,
+    };
+    let mut search_depth = None;
+    let mut exact = 10;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+        let number = |v: String| {
+            v.parse::<u64>()
+                .map_err(|_| format!("invalid value for {}", arg))
+        };
+        match arg.as_str() {
+            "--out" => options.out = value()?,
+            "--init" => options.init = Some(value()?),
+            "--iterations" => options.iterations = number(value()?)? as usize,
+            "--games" => options.games = number(value()?)? as usize,
+            "--depth" => options.depth = number(value()?)? as u32,
+            "--epochs" => options.epochs = number(value()?)? as usize,
+            "--seed" => options.seed = number(value()?)?,
+            "--exact" => exact = number(value()?)? as usize,
+            "--epsilon" => {
+                options.epsilon = value()?
+                    .parse()
+                    .map_err(|_| "invalid value for --epsilon".to_string())?
+            }
+            "--rate" => {
+                options.rate = value()?
+                    .parse()
+                    .map_err(|_| "invalid value for --rate".to_string())?
+            }
+            "--label" => {
+                let v = value()?;
+                match v.split_once(':') {
+                    None if v == "outcome" => search_depth = None,
+                    Some(("search", d)) => search_depth = Some(number(d.to_string())? as u32),
+                    _ => return Err(format!("unknown label {}", v)),
+                }
+            }
+            "--method" => match value()?.as_str() {
+                "regression" => options.td = false,
+                "td" => options.td = true,
+                v => return Err(format!("unknown method {}", v)),
+            },
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    if let Some(depth) = search_depth {
+        options.label = Label::Search { depth, exact };
+    }
+    if !(0.0..=1.0).contains(&options.epsilon) {
+        return Err("--epsilon must be between 0 and 1".to_string());
+    }
+    Ok(options)
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let options = match parse_args(&args) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let mut weights = match &options.init {
+        Some(path) => Weights::load(path).unwrap_or_else(|e| {
+            eprintln!("failed to load {}: {}", path, e);
+            std::process::exit(1);
+        }),
+        None => Weights::new(),
+    };
+    let config = SelfPlayConfig {
+        depth: options.depth,
+        epsilon: options.epsilon,
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(options.seed);
+    for i in 0..options.iterations {
+        let games = self_play(&weights, &config, options.games, &mut rng);
+        if options.td {
+            td_learn(&mut weights, &games, options.rate);
+            println!("round {}: td update on {} games", i + 1, games.len());
+        } else {
+            let samples = label(&games, options.label, &weights);
+            let mse = fit(&mut weights, &samples, options.epochs, options.rate);
+            println!("round {}: {} samples, mse {:.3}", i + 1, samples.len(), mse);
+        }
+        if let Err(e) = weights.save(&options.out) {
+            eprintln!("failed to write {}: {}", options.out, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use reversi_core::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RVWT";
+const VERSION: u32 = 1;
+
+// Base tuples as (x, y) cells. Every tuple is evaluated under all eight board
+// symmetries with one shared table, so the evaluation is symmetric by design.
+const BASE_PATTERNS: &[&[(usize, usize)]] = &[
+    &[
+        (0, 0),
+        (1, 0),
+        (2, 0),
+        (0, 1),
+        (1, 1),
+        (2, 1),
+        (0, 2),
+        (1, 2),
+        (2, 2),
+    ],
+    &[
+        (0, 0),
+        (1, 0),
+        (2, 0),
+        (3, 0),
+        (4, 0),
+        (5, 0),
+        (6, 0),
+        (7, 0),
+    ],
+    &[
+        (0, 1),
+        (1, 1),
+        (2, 1),
+        (3, 1),
+        (4, 1),
+        (5, 1),
+        (6, 1),
+        (7, 1),
+    ],
+    &[
+        (0, 2),
+        (1, 2),
+        (2, 2),
+        (3, 2),
+        (4, 2),
+        (5, 2),
+        (6, 2),
+        (7, 2),
+    ],
+    &[
+        (0, 3),
+        (1, 3),
+        (2, 3),
+        (3, 3),
+        (4, 3),
+        (5, 3),
+        (6, 3),
+        (7, 3),
+    ],
+    &[
+        (0, 0),
+        (1, 1),
+        (2, 2),
+        (3, 3),
+        (4, 4),
+        (5, 5),
+        (6, 6),
+        (7, 7),
+    ],
+    &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 6), (6, 7)],
+    &[(0, 2), (1, 3), (2, 4), (3, 5), (4, 6), (5, 7)],
+    &[(0, 3), (1, 4), (2, 5), (3, 6), (4, 7)],
+    &[(0, 4), (1, 5), (2, 6), (3, 7)],
+];
+
+fn symmetries(x: usize, y: usize) -> [(usize, usize); 8] {
+    [
+        (x, y),
+        (7 - x, y),
+        (x, 7 - y),
+        (7 - x, 7 - y),
+        (y, x),
+        (7 - y, x),
+        (y, 7 - x),
+        (7 - y, 7 - x),
+    ]
+}
+
+struct Pattern {
+    variants: Vec<Vec<Position>>,
+}
+
+impl Pattern {
+    fn new(cells: &[(usize, usize)]) -> Self {
+        let mut variants: Vec<Vec<Position>> = Vec::new();
+        for s in 0..8 {
+            let variant = cells
+                .iter()
+                .map(|&(x, y)| {
+                    let (x, y) = symmetries(x, y)[s];
+                    Position::new(x as i32, y as i32).unwrap()
+                })
+                .collect::<Vec<Position>>();
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+        }
+        Self { variants }
+    }
+    fn size(&self) -> usize {
+        3usize.pow(self.variants[0].len() as u32)
+    }
+    fn indexes<'a>(&'a self, field: &'a Field, color: BorW) -> impl Iterator<Item = usize> + 'a {
+        self.variants.iter().map(move |cells| {
+            cells.iter().fold(0, |index, &p| {
+                index * 3
+                    + match field.get(p) {
+                        Masu::Empty => 0,
+                        Masu::Putted(c) if c == color => 1,
+                        Masu::Putted(_) => 2,
+                    }
+            })
+        })
+    }
+}
+
+/// N-tuple evaluation weights. `evaluate` predicts the final disc difference
+/// from the point of view of `color`, the side to move.
+pub struct Weights {
+    patterns: Vec<Pattern>,
+    tables: Vec<Vec<f32>>,
+    bias: f32,
+}
+
+impl Weights {
+    pub fn new() -> Self {
+        let patterns = BASE_PATTERNS
+            .iter()
+            .map(|cells| Pattern::new(cells))
+            .collect::<Vec<Pattern>>();
+        let tables = patterns.iter().map(|p| vec![0.0; p.size()]).collect();
+        Self {
+            patterns,
+            tables,
+            bias: 0.0,
+        }
+    }
+    pub fn evaluate(&self, field: &Field, color: BorW) -> f32 {
+        let mut value = self.bias;
+        for (pattern, table) in self.patterns.iter().zip(self.tables.iter()) {
+            for index in pattern.indexes(field, color) {
+                value += table[index];
+            }
+        }
+        value
+    }
+    /// Moves every weight that contributes to `evaluate(field, color)` by
+    /// `delta`, spread evenly over the active features.
+    pub fn update(&mut self, field: &Field, color: BorW, delta: f32) {
+        let features = self
+            .patterns
+            .iter()
+            .map(|p| p.variants.len())
+            .sum::<usize>()
+            + 1;
+        let step = delta / features as f32;
+        for (pattern, table) in self.patterns.iter().zip(self.tables.iter_mut()) {
+            for index in pattern.indexes(field, color) {
+                table[index] += step;
+            }
+        }
+        self.bias += step;
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a weight file"));
+        }
+        if read_u32(reader)? != VERSION {
+            return Err(invalid("unsupported weight file version"));
+        }
+        let mut weights = Self::new();
+        if read_u32(reader)? as usize != weights.tables.len() {
+            return Err(invalid("pattern count mismatch"));
+        }
+        for table in weights.tables.iter_mut() {
+            if read_u32(reader)? as usize != table.len() {
+                return Err(invalid("pattern size mismatch"));
+            }
+            for w in table.iter_mut() {
+                *w = read_f32(reader)?;
+            }
+        }
+        weights.bias = read_f32(reader)?;
+        Ok(weights)
+    }
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.tables.len() as u32).to_le_bytes())?;
+        for table in self.tables.iter() {
+            writer.write_all(&(table.len() as u32).to_le_bytes())?;
+            for w in table.iter() {
+                writer.write_all(&w.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&self.bias.to_le_bytes())
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> std::io::Result<f32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
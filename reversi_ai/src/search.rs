@@ -0,0 +1,111 @@
+use crate::{disc_diff, legal_moves, play, Weights};
+use reversi_core::*;
+
+pub trait Evaluator {
+    fn evaluate(&self, field: &Field, color: BorW) -> f32;
+}
+
+impl Evaluator for Weights {
+    fn evaluate(&self, field: &Field, color: BorW) -> f32 {
+        Weights::evaluate(self, field, color)
+    }
+}
+
+/// Plain disc difference; the evaluation used when no weights are loaded.
+pub struct DiscCount;
+
+impl Evaluator for DiscCount {
+    fn evaluate(&self, field: &Field, color: BorW) -> f32 {
+        disc_diff(field, color)
+    }
+}
+
+/// Score of `field` for `color` to move, searched `depth` plies deep.
+/// Finished games are scored exactly by their disc difference.
+pub fn negamax<E: Evaluator>(field: &Field, color: BorW, depth: u32, eval: &E) -> f32 {
+    alphabeta(
+        field,
+        color,
+        depth,
+        -f32::INFINITY,
+        f32::INFINITY,
+        eval,
+        false,
+    )
+}
+
+fn alphabeta<E: Evaluator>(
+    field: &Field,
+    color: BorW,
+    depth: u32,
+    mut alpha: f32,
+    beta: f32,
+    eval: &E,
+    passed: bool,
+) -> f32 {
+    let moves = legal_moves(field, color);
+    if moves.is_empty() {
+        if passed {
+            return disc_diff(field, color);
+        }
+        return -alphabeta(
+            field,
+            get_another_color(color),
+            depth,
+            -beta,
+            -alpha,
+            eval,
+            true,
+        );
+    }
+    if depth == 0 {
+        return eval.evaluate(field, color);
+    }
+    let mut best = -f32::INFINITY;
+    for p in moves {
+        let next = play(field, p, color);
+        let score = -alphabeta(
+            &next,
+            get_another_color(color),
+            depth - 1,
+            -beta,
+            -alpha,
+            eval,
+            false,
+        );
+        if best < score {
+            best = score;
+        }
+        if alpha < score {
+            alpha = score;
+        }
+        if beta <= alpha {
+            break;
+        }
+    }
+    best
+}
+
+pub fn best_move<E: Evaluator>(
+    field: &Field,
+    color: BorW,
+    depth: u32,
+    eval: &E,
+) -> Option<Position> {
+    let mut best = None;
+    let mut best_score = -f32::INFINITY;
+    for p in legal_moves(field, color) {
+        let next = play(field, p, color);
+        let score = -negamax(
+            &next,
+            get_another_color(color),
+            depth.saturating_sub(1),
+            eval,
+        );
+        if best.is_none() || best_score < score {
+            best = Some(p);
+            best_score = score;
+        }
+    }
+    best
+}
@@ -0,0 +1,73 @@
+use crate::{best_move, legal_moves, play, Weights};
+use rand::Rng;
+use reversi_core::*;
+
+pub struct SelfPlayConfig {
+    /// Search depth used to pick moves.
+    pub depth: u32,
+    /// Probability of playing a random legal move instead of the best one,
+    /// which keeps the generated positions varied.
+    pub epsilon: f64,
+}
+
+/// One self-play game: every position reached with the colour to move, and
+/// the final disc count of both colours.
+pub struct PlayedGame {
+    pub positions: Vec<(Field, BorW)>,
+    pub black: usize,
+    pub white: usize,
+}
+
+impl PlayedGame {
+    /// Final disc difference from the point of view of `color`.
+    pub fn outcome(&self, color: BorW) -> f32 {
+        match color {
+            BorW::Black => self.black as f32 - self.white as f32,
+            BorW::White => self.white as f32 - self.black as f32,
+        }
+    }
+}
+
+pub fn self_play_game<R: Rng>(
+    weights: &Weights,
+    config: &SelfPlayConfig,
+    rng: &mut R,
+) -> PlayedGame {
+    let mut field = Field::new();
+    let mut turn = BorW::Black;
+    let mut positions = Vec::new();
+    loop {
+        let moves = legal_moves(&field, turn);
+        if moves.is_empty() {
+            turn = get_another_color(turn);
+            if !field.puttable(turn) {
+                break;
+            }
+            continue;
+        }
+        positions.push((field.clone(), turn));
+        let p = if rng.gen_bool(config.epsilon) {
+            moves[rng.gen_range(0..moves.len())]
+        } else {
+            best_move(&field, turn, config.depth, weights).unwrap()
+        };
+        field = play(&field, p, turn);
+        turn = get_another_color(turn);
+    }
+    PlayedGame {
+        positions,
+        black: field.count(BorW::Black),
+        white: field.count(BorW::White),
+    }
+}
+
+pub fn self_play<R: Rng>(
+    weights: &Weights,
+    config: &SelfPlayConfig,
+    games: usize,
+    rng: &mut R,
+) -> Vec<PlayedGame> {
+    (0..games)
+        .map(|_| self_play_game(weights, config, rng))
+        .collect()
+}
@@ -0,0 +1,74 @@
+use crate::{empties, negamax, PlayedGame, Weights};
+use reversi_core::*;
+
+#[derive(Copy, Clone)]
+pub enum Label {
+    /// Final disc difference of the game the position was played in.
+    Outcome,
+    /// Score of a search with the current weights; positions with at most
+    /// `exact` empty squares are solved to the end instead.
+    Search { depth: u32, exact: usize },
+}
+
+pub struct Sample {
+    pub field: Field,
+    pub color: BorW,
+    pub target: f32,
+}
+
+pub fn label(games: &[PlayedGame], label: Label, weights: &Weights) -> Vec<Sample> {
+    games
+        .iter()
+        .flat_map(|game| {
+            game.positions.iter().map(move |(field, color)| {
+                let target = match label {
+                    Label::Outcome => game.outcome(*color),
+                    Label::Search { exact, .. } if empties(field) <= exact => {
+                        negamax(field, *color, exact as u32, weights)
+                    }
+                    Label::Search { depth, .. } => negamax(field, *color, depth, weights),
+                };
+                Sample {
+                    field: field.clone(),
+                    color: *color,
+                    target,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Least-squares regression of the weights onto the sample targets by
+/// stochastic gradient descent. Returns the mean squared error of the last
+/// epoch.
+pub fn fit(weights: &mut Weights, samples: &[Sample], epochs: usize, learning_rate: f32) -> f32 {
+    let mut mse = 0.0;
+    for _ in 0..epochs {
+        mse = 0.0;
+        for sample in samples {
+            let error = sample.target - weights.evaluate(&sample.field, sample.color);
+            mse += error * error;
+            weights.update(&sample.field, sample.color, learning_rate * error);
+        }
+        mse /= samples.len().max(1) as f32;
+    }
+    mse
+}
+
+/// TD(0): every position is pulled towards the value of the next position in
+/// the same game, and the last one towards the final result.
+pub fn td_learn(weights: &mut Weights, games: &[PlayedGame], learning_rate: f32) {
+    for game in games {
+        for (i, (field, color)) in game.positions.iter().enumerate() {
+            let target = match game.positions.get(i + 1) {
+                Some((next, next_color)) if next_color == color => {
+                    weights.evaluate(next, *next_color)
+                }
+                Some((next, next_color)) => -weights.evaluate(next, *next_color),
+                None => game.outcome(*color),
+            };
+            let error = target - weights.evaluate(field, *color);
+            weights.update(field, *color, learning_rate * error);
+        }
+    }
+}
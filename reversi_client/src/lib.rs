@@ -1,12 +1,175 @@
+use reversi_core::{BorW, GameResult, Masu, Position, ReversiData};
 use reversi_message::*;
 use reversi_wasm_common::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{HtmlButtonElement, HtmlCanvasElement, HtmlDivElement};
+use web_sys::{HtmlButtonElement, HtmlCanvasElement, HtmlDivElement, HtmlInputElement};
 use web_sys::{MessageEvent, WebSocket};
 
-fn create_button(ws: &WebSocket) {
+/// Used when the page is not served by reversi_server, e.g. from a file.
+const SERVER_URL: &str = "ws://127.0.0.1:9001";
+const RETRY_DELAY_MS: i32 = 500;
+const MAX_RETRY_DELAY_MS: i32 = 30_000;
+const CLIENT_NAME: &str = concat!("reversi_client ", env!("CARGO_PKG_VERSION"));
+/// Encodings asked for in `Hello`, most preferred first.
+const ENCODINGS: [Encoding; 2] = [Encoding::MessagePack, Encoding::Json];
+/// Local storage key of the session token, which holds the player's rating.
+const TOKEN_KEY: &str = "reversi_token";
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// The WebSocket of the server that served this page, over TLS if the page
+/// was.
+fn server_url() -> String {
+    let location = web_sys::window().unwrap().location();
+    let scheme = match location.protocol().as_deref() {
+        Ok("http:") => "ws",
+        Ok("https:") => "wss",
+        _ => return SERVER_URL.to_string(),
+    };
+    match location.host() {
+        Ok(host) if !host.is_empty() => format!("{}://{}/", scheme, host),
+        _ => SERVER_URL.to_string(),
+    }
+}
+
+/// The server socket, replaced by a new one whenever it closes.
+#[derive(Clone)]
+struct Connection {
+    ws: Rc<RefCell<Option<WebSocket>>>,
+    token: Rc<Cell<Option<SessionToken>>>,
+    retry_delay: Rc<Cell<i32>>,
+    /// Set when the server turned this client down; retrying cannot help.
+    rejected: Rc<Cell<bool>>,
+    /// Agreed on in `Hello`; JSON until the server has answered.
+    encoding: Rc<Cell<Encoding>>,
+}
+
+impl Connection {
+    fn new() -> Self {
+        Connection {
+            ws: Rc::new(RefCell::new(None)),
+            token: Rc::new(Cell::new(
+                local_storage()
+                    .and_then(|x| x.get_item(TOKEN_KEY).ok()?)
+                    .and_then(|x| serde_json::from_str(&x).ok()),
+            )),
+            retry_delay: Rc::new(Cell::new(RETRY_DELAY_MS)),
+            rejected: Rc::new(Cell::new(false)),
+            encoding: Rc::new(Cell::new(Encoding::Json)),
+        }
+    }
+    /// Remembers the session across page loads as well as reconnects.
+    fn set_token(&self, token: Option<SessionToken>) {
+        self.token.set(token);
+        if let Some(storage) = local_storage() {
+            let _ = match token {
+                Some(x) => storage.set_item(TOKEN_KEY, &serde_json::to_string(&x).unwrap()),
+                None => storage.remove_item(TOKEN_KEY),
+            };
+        }
+    }
+    fn send(&self, message: &ClientMessage) {
+        // Messages sent while reconnecting are dropped; the server sends the
+        // current state again once the session is resumed.
+        if let Some(ws) = self.ws.borrow().as_ref() {
+            let encoding = self.encoding.get();
+            let bytes = encoding.encode(message).unwrap();
+            let _ = if encoding.is_binary() {
+                ws.send_with_u8_array(&bytes)
+            } else {
+                ws.send_with_str(&String::from_utf8(bytes).unwrap())
+            };
+        }
+    }
+    /// Reads a text frame as JSON and a binary frame in the agreed encoding.
+    fn decode(&self, data: JsValue) -> Option<ServerMessage> {
+        match data.as_string() {
+            Some(x) => serde_json::from_str(&x).ok(),
+            None => {
+                let bytes = js_sys::Uint8Array::new(&data).to_vec();
+                self.encoding.get().decode(&bytes).ok()
+            }
+        }
+    }
+    /// Opens a socket, resuming the session if there is one, and schedules
+    /// the next attempt with a doubled delay when it closes.
+    fn open(&self, onmessage: &js_sys::Function) -> Result<(), JsValue> {
+        let ws = WebSocket::new(&server_url())?;
+        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        ws.set_onmessage(Some(onmessage));
+        let conn = self.clone();
+        let open_callback = Closure::once_into_js(move |_: web_sys::Event| {
+            conn.retry_delay.set(RETRY_DELAY_MS);
+            get_or_create_div("error").set_inner_text("");
+            conn.encoding.set(Encoding::Json);
+            conn.send(&ClientMessage::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_name: CLIENT_NAME.to_string(),
+                encodings: ENCODINGS.to_vec(),
+            });
+            if let Some(token) = conn.token.get() {
+                conn.send(&ClientMessage::Resume(token));
+            }
+            conn.send(&ClientMessage::SessionList {});
+        });
+        ws.set_onopen(Some(open_callback.unchecked_ref()));
+        let conn = self.clone();
+        let onmessage = onmessage.clone();
+        let close_callback = Closure::once_into_js(move |_: web_sys::Event| {
+            if conn.rejected.get() {
+                return;
+            }
+            let delay = conn.retry_delay.get();
+            conn.retry_delay.set((delay * 2).min(MAX_RETRY_DELAY_MS));
+            get_or_create_div("error").set_inner_text("connection lost, reconnecting...");
+            let retry = Closure::once_into_js(move || {
+                let _ = conn.open(&onmessage);
+            });
+            web_sys::window()
+                .unwrap()
+                .set_timeout_with_callback_and_timeout_and_arguments_0(retry.unchecked_ref(), delay)
+                .unwrap();
+        });
+        ws.set_onclose(Some(close_callback.unchecked_ref()));
+        *self.ws.borrow_mut() = Some(ws);
+        Ok(())
+    }
+}
+
+/// Reads "10" as ten minutes sudden death, "5+3" as five minutes plus three
+/// seconds a move and "10/30x3" as ten minutes then three 30 second periods.
+/// Anything else means no time limit.
+fn parse_time_control(text: &str) -> TimeControl {
+    let minutes = |x: &str| x.trim().parse::<u64>().ok().map(|x| x * 60_000);
+    let seconds = |x: &str| x.trim().parse::<u64>().ok().map(|x| x * 1_000);
+    let parsed = if let Some((main, increment)) = text.split_once('+') {
+        minutes(main)
+            .zip(seconds(increment))
+            .map(|(main_ms, increment_ms)| TimeControl::Fischer {
+                main_ms,
+                increment_ms,
+            })
+    } else if let Some((main, byoyomi)) = text.split_once('/') {
+        byoyomi.split_once('x').and_then(|(period, periods)| {
+            Some(TimeControl::ByoYomi {
+                main_ms: minutes(main)?,
+                period_ms: seconds(period)?,
+                periods: periods.trim().parse().ok()?,
+            })
+        })
+    } else {
+        minutes(text).map(|main_ms| TimeControl::SuddenDeath { main_ms })
+    };
+    parsed.unwrap_or_default()
+}
+
+fn create_button(conn: &Connection) {
     let document = web_sys::window().unwrap().document().unwrap();
     let body = document.body().unwrap();
     let buttondiv = document
@@ -19,13 +182,23 @@ fn create_button(ws: &WebSocket) {
         .unwrap()
         .dyn_into::<HtmlButtonElement>()
         .unwrap();
+    let time_input = document
+        .create_element("input")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()
+        .unwrap();
+    time_input.set_placeholder("time: 10, 5+3 or 10/30x3");
     buttondiv.append_child(&button).unwrap();
+    buttondiv.append_child(&time_input).unwrap();
     body.append_child(&buttondiv).unwrap();
     button.set_inner_html("create new game");
-    let ws1 = ws.clone();
+    let conn1 = conn.clone();
+    let time_input1 = time_input.clone();
     let button_click_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
-        ws1.send_with_str(&serde_json::to_string(&ClientMessage::CreateGame {}).unwrap())
-            .unwrap();
+        conn1.send(&ClientMessage::CreateGame(GameOptions {
+            time_control: parse_time_control(&time_input1.value()),
+            ..Default::default()
+        }));
     });
     button
         .add_event_listener_with_callback("click", button_click_callback.as_ref().unchecked_ref())
@@ -39,10 +212,9 @@ fn create_button(ws: &WebSocket) {
         .unwrap();
     buttondiv.append_child(&reset_button).unwrap();
     reset_button.set_inner_html("reset");
-    let ws2 = ws.clone();
+    let conn2 = conn.clone();
     let reset_button_click_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
-        ws2.send_with_str(&serde_json::to_string(&ClientMessage::Reset {}).unwrap())
-            .unwrap();
+        conn2.send(&ClientMessage::Reset {});
     });
     reset_button
         .add_event_listener_with_callback(
@@ -51,6 +223,140 @@ fn create_button(ws: &WebSocket) {
         )
         .unwrap();
     reset_button_click_callback.forget();
+
+    message_button(&buttondiv, "resign", conn, ClientMessage::Resign);
+    message_button(
+        &buttondiv,
+        "offer draw",
+        conn,
+        ClientMessage::Offer(Proposal::Draw),
+    );
+    message_button(
+        &buttondiv,
+        "take back",
+        conn,
+        ClientMessage::Offer(Proposal::Takeback),
+    );
+    message_button(
+        &buttondiv,
+        "rematch",
+        conn,
+        ClientMessage::Offer(Proposal::Rematch),
+    );
+
+    let level_input = document
+        .create_element("input")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()
+        .unwrap();
+    level_input.set_type("number");
+    level_input.set_min("1");
+    level_input.set_max(&MAX_COMPUTER_LEVEL.to_string());
+    level_input.set_value("2");
+    let computer_button = document
+        .create_element("button")
+        .unwrap()
+        .dyn_into::<HtmlButtonElement>()
+        .unwrap();
+    buttondiv.append_child(&computer_button).unwrap();
+    buttondiv.append_child(&level_input).unwrap();
+    computer_button.set_inner_html("play computer, level");
+    let conn6 = conn.clone();
+    let computer_button_click_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+        let level = level_input.value().parse().unwrap_or(1);
+        conn6.send(&ClientMessage::CreateGame(GameOptions {
+            opponent: Opponent::Computer { level },
+            time_control: parse_time_control(&time_input.value()),
+        }));
+    });
+    computer_button
+        .add_event_listener_with_callback(
+            "click",
+            computer_button_click_callback.as_ref().unchecked_ref(),
+        )
+        .unwrap();
+    computer_button_click_callback.forget();
+
+    let match_button = document
+        .create_element("button")
+        .unwrap()
+        .dyn_into::<HtmlButtonElement>()
+        .unwrap();
+    buttondiv.append_child(&match_button).unwrap();
+    match_button.set_inner_html("find match");
+    let conn3 = conn.clone();
+    let match_button_click_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+        conn3.send(&ClientMessage::FindMatch);
+    });
+    match_button
+        .add_event_listener_with_callback(
+            "click",
+            match_button_click_callback.as_ref().unchecked_ref(),
+        )
+        .unwrap();
+    match_button_click_callback.forget();
+
+    let cancel_button = document
+        .create_element("button")
+        .unwrap()
+        .dyn_into::<HtmlButtonElement>()
+        .unwrap();
+    buttondiv.append_child(&cancel_button).unwrap();
+    cancel_button.set_inner_html("cancel search");
+    let conn4 = conn.clone();
+    let cancel_button_click_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+        conn4.send(&ClientMessage::CancelMatch);
+    });
+    cancel_button
+        .add_event_listener_with_callback(
+            "click",
+            cancel_button_click_callback.as_ref().unchecked_ref(),
+        )
+        .unwrap();
+    cancel_button_click_callback.forget();
+
+    let leaderboard_button = document
+        .create_element("button")
+        .unwrap()
+        .dyn_into::<HtmlButtonElement>()
+        .unwrap();
+    buttondiv.append_child(&leaderboard_button).unwrap();
+    leaderboard_button.set_inner_html("leaderboard");
+    let conn5 = conn.clone();
+    let leaderboard_button_click_callback =
+        Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+            conn5.send(&ClientMessage::Leaderboard);
+        });
+    leaderboard_button
+        .add_event_listener_with_callback(
+            "click",
+            leaderboard_button_click_callback.as_ref().unchecked_ref(),
+        )
+        .unwrap();
+    leaderboard_button_click_callback.forget();
+
+    let hide_input = document
+        .create_element("input")
+        .unwrap()
+        .dyn_into::<HtmlInputElement>()
+        .unwrap();
+    hide_input.set_type("checkbox");
+    hide_input.set_id("hide_over");
+    buttondiv.append_child(&hide_input).unwrap();
+    let hide_label = document.create_element("span").unwrap();
+    hide_label.set_inner_html("hide finished games");
+    buttondiv.append_child(&hide_label).unwrap();
+    let conn7 = conn.clone();
+    let hide_input_change_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+        conn7.send(&ClientMessage::SessionList);
+    });
+    hide_input
+        .add_event_listener_with_callback(
+            "change",
+            hide_input_change_callback.as_ref().unchecked_ref(),
+        )
+        .unwrap();
+    hide_input_change_callback.forget();
 }
 
 fn game_list_view(list: &Vec<GameSummary>, listener: &::js_sys::Function) {
@@ -69,7 +375,14 @@ fn game_list_view(list: &Vec<GameSummary>, listener: &::js_sys::Function) {
             x
         }
     };
-    list.iter().enumerate().for_each(|(_, gs)| {
+    let hide_over = document
+        .get_element_by_id("hide_over")
+        .and_then(|x| x.dyn_into::<HtmlInputElement>().ok())
+        .is_some_and(|x| x.checked());
+    let shown = list.iter().filter(|gs| {
+        gs.your || !hide_over || !matches!(gs.state, GameState::Finished | GameState::Abandoned)
+    });
+    shown.enumerate().for_each(|(_, gs)| {
         let div = document
             .create_element("div")
             .unwrap()
@@ -78,7 +391,14 @@ fn game_list_view(list: &Vec<GameSummary>, listener: &::js_sys::Function) {
         listdiv.append_child(&div).unwrap();
         div.set_attribute("game_id", &format!("{}", gs.id.0))
             .unwrap();
-        let mut inner = format!("id: {} , number : {}", gs.id.0, gs.members);
+        let mut inner = format!(
+            "id: {} , {:?} , number : {} , {} vs {}",
+            gs.id.0,
+            gs.state,
+            gs.members,
+            gs.players.black.as_deref().unwrap_or("-"),
+            gs.players.white.as_deref().unwrap_or("-")
+        );
         if gs.your {
             inner = inner + "★";
         }
@@ -88,6 +408,275 @@ fn game_list_view(list: &Vec<GameSummary>, listener: &::js_sys::Function) {
     });
 }
 
+fn get_or_create_div(id: &str) -> HtmlDivElement {
+    let document = web_sys::window().unwrap().document().unwrap();
+    match document.get_element_by_id(id) {
+        None => {
+            let x = document
+                .create_element("div")
+                .unwrap()
+                .dyn_into::<HtmlDivElement>()
+                .unwrap();
+            x.set_id(id);
+            document.body().unwrap().append_child(&x).unwrap();
+            x
+        }
+        Some(x) => x.dyn_into::<HtmlDivElement>().unwrap(),
+    }
+}
+
+fn role_view(role: &Role) {
+    get_or_create_div("role").set_inner_text(match role {
+        Role::Player(BorW::Black) => "you play black",
+        Role::Player(BorW::White) => "you play white",
+        Role::Spectator => "spectating",
+    });
+}
+
+fn players_view(players: &Players) {
+    let mut text = format!(
+        "black: {} / white: {}",
+        players.black.as_deref().unwrap_or("-"),
+        players.white.as_deref().unwrap_or("-")
+    );
+    if !players.spectators.is_empty() {
+        text.push_str(&format!(" / watching: {}", players.spectators.join(", ")));
+    }
+    get_or_create_div("players").set_inner_text(&text);
+}
+
+fn create_login_form(conn: &Connection) -> Result<(), JsValue> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let form = get_or_create_div("login");
+    let input = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    input.set_placeholder("nickname");
+    input.set_max_length(MAX_NICKNAME_LENGTH as i32);
+    form.append_child(&input)?;
+    let button = document
+        .create_element("button")?
+        .dyn_into::<HtmlButtonElement>()?;
+    button.set_inner_html("login");
+    form.append_child(&button)?;
+    let conn = conn.clone();
+    let login_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+        conn.send(&ClientMessage::Login {
+            nickname: input.value(),
+        });
+    });
+    button.add_event_listener_with_callback("click", login_callback.as_ref().unchecked_ref())?;
+    login_callback.forget();
+    Ok(())
+}
+
+/// Both clocks, counting down the running one from when `state` arrived.
+fn clock_view(clock: &Option<(ClockState, f64)>) {
+    let (state, received_at) = match clock {
+        Some(x) => x,
+        None => {
+            get_or_create_div("clock").set_inner_text("");
+            return;
+        }
+    };
+    let since = (js_sys::Date::now() - received_at).max(0.0) as u64;
+    let show = |color| match state.left(color, since) {
+        Some((ms, periods)) => {
+            let secs = ms.div_ceil(1000);
+            let mut text = format!("{}:{:02}", secs / 60, secs % 60);
+            if state.period_ms > 0 {
+                text.push_str(&format!(" ({}x{}s)", periods, state.period_ms / 1000));
+            }
+            text
+        }
+        None => "0:00".to_string(),
+    };
+    let mark = |color| {
+        if state.running == Some(color) {
+            "*"
+        } else {
+            ""
+        }
+    };
+    get_or_create_div("clock").set_inner_text(&format!(
+        "black {}{} / white {}{}",
+        show(BorW::Black),
+        mark(BorW::Black),
+        show(BorW::White),
+        mark(BorW::White)
+    ));
+}
+
+fn game_view(data: &ReversiData, canvas: &HtmlCanvasElement) {
+    get_or_create_div("error").set_inner_text("");
+    if matches!(data.field.get_gameresult(), GameResult::Playing) {
+        get_or_create_div("result").set_inner_text("");
+    }
+    get_or_create_div("offer").set_inner_text("");
+    view(data, canvas);
+}
+
+/// Applies a `MoveApplied` update to the board.
+fn apply_move(
+    data: &mut ReversiData,
+    color: BorW,
+    position: Position,
+    flipped: &[Position],
+    turn: BorW,
+) {
+    data.field.set(position, Masu::Putted(color));
+    for p in flipped {
+        data.field.set(*p, Masu::Putted(color));
+    }
+    data.cursor = position;
+    data.turn = turn;
+}
+
+/// A button that sends `message` when clicked.
+fn message_button(div: &HtmlDivElement, label: &str, conn: &Connection, message: ClientMessage) {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let button = document
+        .create_element("button")
+        .unwrap()
+        .dyn_into::<HtmlButtonElement>()
+        .unwrap();
+    button.set_inner_html(label);
+    div.append_child(&button).unwrap();
+    let conn = conn.clone();
+    let click_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+        conn.send(&message);
+    });
+    button
+        .add_event_listener_with_callback("click", click_callback.as_ref().unchecked_ref())
+        .unwrap();
+    click_callback.forget();
+}
+
+fn proposal_name(proposal: Proposal) -> &'static str {
+    match proposal {
+        Proposal::Draw => "a draw",
+        Proposal::Takeback => "a takeback",
+        Proposal::Rematch => "a rematch",
+        Proposal::Reset => "a reset",
+    }
+}
+
+fn color_name(color: BorW) -> &'static str {
+    match color {
+        BorW::Black => "black",
+        BorW::White => "white",
+    }
+}
+
+/// An offer, with buttons to answer it unless it is our own.
+fn offer_view(conn: &Connection, proposal: Proposal, by: BorW, mine: bool) {
+    let div = get_or_create_div("offer");
+    div.set_inner_text(&format!(
+        "{} offers {} ",
+        color_name(by),
+        proposal_name(proposal)
+    ));
+    if !mine {
+        message_button(&div, "accept", conn, ClientMessage::Accept(proposal));
+        message_button(&div, "decline", conn, ClientMessage::Decline(proposal));
+    }
+}
+
+fn game_over_view(winner: Option<BorW>, reason: EndReason, rating_changes: &[RatingChange]) {
+    let mut text = match winner {
+        Some(BorW::Black) => "black wins".to_string(),
+        Some(BorW::White) => "white wins".to_string(),
+        None => "draw".to_string(),
+    };
+    text.push_str(match reason {
+        EndReason::Board => "",
+        EndReason::Time => " on time",
+        EndReason::Resignation => " by resignation",
+        EndReason::Agreement => " by agreement",
+    });
+    for x in rating_changes {
+        text.push_str(&format!(" / {}: {} -> {}", x.nickname, x.old, x.new));
+    }
+    get_or_create_div("result").set_inner_text(&text);
+}
+
+fn leaderboard_view(top: &[LeaderboardEntry], you: &Option<LeaderboardEntry>) {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let board = get_or_create_div("leaderboard");
+    board.set_inner_text("");
+    let line = |x: &LeaderboardEntry| {
+        let div = document
+            .create_element("div")
+            .unwrap()
+            .dyn_into::<HtmlDivElement>()
+            .unwrap();
+        div.set_inner_text(&format!("{}. {} ({})", x.rank, x.nickname, x.rating));
+        board.append_child(&div).unwrap();
+    };
+    top.iter().for_each(line);
+    if let Some(x) = you.as_ref().filter(|x| x.rank as usize > top.len()) {
+        line(x);
+    }
+}
+
+/// Chat log and input, placed beside the board.
+fn create_chat_panel(conn: &Connection) -> Result<(), JsValue> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let panel = get_or_create_div("chat");
+    panel.style().set_property("display", "inline-block")?;
+    panel.style().set_property("vertical-align", "top")?;
+    panel.style().set_property("width", "20em")?;
+    let log = document
+        .create_element("div")?
+        .dyn_into::<HtmlDivElement>()?;
+    log.set_id("chatlog");
+    log.style().set_property("height", "20em")?;
+    log.style().set_property("overflow-y", "auto")?;
+    panel.append_child(&log)?;
+    let input = document
+        .create_element("input")?
+        .dyn_into::<HtmlInputElement>()?;
+    input.set_max_length(MAX_CHAT_LENGTH as i32);
+    panel.append_child(&input)?;
+    let conn = conn.clone();
+    let input_clone = input.clone();
+    let keydown_callback = Closure::<dyn FnMut(_)>::new(move |e: web_sys::KeyboardEvent| {
+        if e.key() != "Enter" || input_clone.value().is_empty() {
+            return;
+        }
+        conn.send(&ClientMessage::Chat(input_clone.value()));
+        input_clone.set_value("");
+    });
+    input.add_event_listener_with_callback("keydown", keydown_callback.as_ref().unchecked_ref())?;
+    keydown_callback.forget();
+    Ok(())
+}
+
+/// Empties the chat log before the server replays the scrollback of a room.
+fn clear_chat() {
+    get_or_create_div("chatlog").set_inner_text("");
+}
+
+fn chat_view(from: &str, text: &str, timestamp: u64) {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let log = get_or_create_div("chatlog");
+    let time = js_sys::Date::new(&JsValue::from_f64(timestamp as f64));
+    let line = document
+        .create_element("div")
+        .unwrap()
+        .dyn_into::<HtmlDivElement>()
+        .unwrap();
+    line.set_inner_text(&format!(
+        "[{:02}:{:02}] {}: {}",
+        time.get_hours(),
+        time.get_minutes(),
+        from,
+        text
+    ));
+    log.append_child(&line).unwrap();
+    log.set_scroll_top(log.scroll_height());
+}
+
 #[wasm_bindgen(start)]
 pub fn run() -> Result<(), JsValue> {
     let document = web_sys::window().unwrap().document().unwrap();
@@ -97,66 +686,173 @@ pub fn run() -> Result<(), JsValue> {
         .dyn_into::<HtmlCanvasElement>()?;
     canvas.set_width(0);
     canvas.set_height(0);
+    canvas.style().set_property("vertical-align", "top")?;
     body.append_child(&canvas)?;
-    let ws = WebSocket::new("ws://127.0.0.1:9001")?;
-    let ws_clone = ws.clone();
-    create_button(&ws_clone);
+    let conn = Connection::new();
+    create_chat_panel(&conn)?;
+    create_login_form(&conn)?;
+    create_button(&conn);
 
-    let ws_clone = ws.clone();
+    let conn_clone = conn.clone();
     let mouse_callback = Closure::<dyn FnMut(_)>::new(move |e: web_sys::MouseEvent| {
         if let Ok(x) = mouseinput(e) {
-            ws_clone
-                .send_with_str(&serde_json::to_string(&ClientMessage::Put(x)).unwrap())
-                .unwrap();
+            conn_clone.send(&ClientMessage::Put(x));
         }
     });
     canvas
         .add_event_listener_with_callback("mousedown", mouse_callback.as_ref().unchecked_ref())?;
     mouse_callback.forget();
 
-    let ws_clone = ws.clone();
+    let conn_clone = conn.clone();
     let select_game_callback = Closure::<dyn FnMut(_)>::new(move |e: web_sys::MouseEvent| {
         let tmp = e.target();
         let target = tmp.unwrap();
         let target1 = target.dyn_into::<web_sys::HtmlElement>().unwrap();
         let html = target1.get_attribute("game_id").unwrap();
         let id: u32 = html.parse().unwrap();
-        ws_clone
-            .send_with_str(&serde_json::to_string(&ClientMessage::SelectGame(GameID(id))).unwrap())
-            .unwrap();
+        conn_clone.send(&ClientMessage::SelectGame(GameID(id)));
     });
+    let clock = Rc::new(RefCell::new(None));
+    let clock_clone = clock.clone();
+    let tick_callback = Closure::<dyn FnMut()>::new(move || clock_view(&clock_clone.borrow()));
+    web_sys::window()
+        .unwrap()
+        .set_interval_with_callback_and_timeout_and_arguments_0(
+            tick_callback.as_ref().unchecked_ref(),
+            200,
+        )?;
+    tick_callback.forget();
+
+    let my_color = Cell::new(None);
+    let mut board: Option<(u64, ReversiData)> = None;
+    let conn_clone = conn.clone();
     let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
-        let jsdata: String = e
-            .data()
-            .dyn_into::<js_sys::JsString>()
-            .unwrap()
-            .as_string()
-            .unwrap();
-        let server_message: ServerMessage;
-        match serde_json::from_str(&jsdata) {
-            Ok(x) => server_message = x,
-            Err(_) => return,
-        }
+        let server_message = match conn_clone.decode(e.data()) {
+            Some(x) => x,
+            None => return,
+        };
         match server_message {
+            ServerMessage::Hello { encoding, .. } => {
+                conn_clone.encoding.set(encoding);
+                // Every new connection, resumed or not, replays the lobby.
+                clear_chat();
+            }
+            ServerMessage::Rejected { reason, .. } => {
+                conn_clone.rejected.set(true);
+                get_or_create_div("error")
+                    .set_inner_text(&format!("the server cannot serve this page: {}", reason));
+            }
             ServerMessage::GameList(x) => {
                 game_list_view(&x, select_game_callback.as_ref().unchecked_ref());
             }
-            ServerMessage::View(x) => {
-                view(&x, &canvas);
+            ServerMessage::View {
+                seq,
+                data,
+                clock: state,
+            } => {
+                game_view(&data, &canvas);
+                *clock.borrow_mut() = state.map(|x| (x, js_sys::Date::now()));
+                clock_view(&clock.borrow());
+                board = Some((seq, data));
+            }
+            ServerMessage::MoveApplied {
+                seq,
+                color,
+                position,
+                flipped,
+                turn,
+                clock: state,
+            } => match board.as_mut() {
+                Some((last, data)) if *last + 1 == seq => {
+                    apply_move(data, color, position, &flipped, turn);
+                    *last = seq;
+                    game_view(data, &canvas);
+                    *clock.borrow_mut() = state.map(|x| (x, js_sys::Date::now()));
+                    clock_view(&clock.borrow());
+                }
+                // An update went missing, so the board is out of date.
+                _ => conn_clone.send(&ClientMessage::Resync),
+            },
+            ServerMessage::Role(x) => {
+                // Joining a game is followed by the scrollback of its room.
+                clear_chat();
+                my_color.set(match x {
+                    Role::Player(color) => Some(color),
+                    Role::Spectator => None,
+                });
+                role_view(&x);
+            }
+            ServerMessage::Offered { proposal, by } => {
+                offer_view(&conn_clone, proposal, by, my_color.get() == Some(by));
+            }
+            ServerMessage::Declined { proposal, by } => {
+                get_or_create_div("offer").set_inner_text(&format!(
+                    "{} declined {}",
+                    color_name(by),
+                    proposal_name(proposal)
+                ));
+            }
+            ServerMessage::Welcome { nickname, token } => {
+                conn_clone.set_token(Some(token));
+                get_or_create_div("error").set_inner_text("");
+                get_or_create_div("login").set_inner_text(&format!("logged in as {}", nickname));
+            }
+            ServerMessage::Players(x) => {
+                players_view(&x);
             }
+            ServerMessage::Searching => {
+                get_or_create_div("search").set_inner_text("searching for an opponent...");
+            }
+            ServerMessage::SearchCancelled => {
+                get_or_create_div("search").set_inner_text("");
+            }
+            ServerMessage::GameOver {
+                winner,
+                reason,
+                rating_changes,
+            } => {
+                // Freeze the clocks where they stood when the game ended.
+                if let Some((x, received_at)) = clock.borrow_mut().as_mut() {
+                    if let Some(color) = x.running {
+                        let since = (js_sys::Date::now() - *received_at).max(0.0) as u64;
+                        let left = x.left(color, since).unwrap_or((0, 0));
+                        match color {
+                            BorW::Black => (x.black_ms, x.black_periods) = left,
+                            BorW::White => (x.white_ms, x.white_periods) = left,
+                        }
+                    }
+                    *received_at = js_sys::Date::now();
+                    x.elapsed_ms = 0;
+                    x.running = None;
+                }
+                game_over_view(winner, reason, &rating_changes);
+            }
+            ServerMessage::Leaderboard { top, you } => {
+                leaderboard_view(&top, &you);
+            }
+            ServerMessage::MatchFound { opponent, .. } => {
+                get_or_create_div("search")
+                    .set_inner_text(&format!("playing against {}", opponent));
+            }
+            ServerMessage::Error { code, message } => {
+                if code == ErrorCode::UnknownSession {
+                    conn_clone.set_token(None);
+                }
+                get_or_create_div("error").set_inner_text(&message);
+            }
+            ServerMessage::ChatMessage {
+                from,
+                text,
+                timestamp,
+            } => {
+                chat_view(&from, &text, timestamp);
+            }
+            // Sent by a newer server; nothing here depends on it.
+            ServerMessage::Unknown(_) => {}
         }
     });
-    ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+    conn.open(onmessage_callback.as_ref().unchecked_ref())?;
     onmessage_callback.forget();
 
-    let ws_clone = ws.clone();
-    let open_callback = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
-        ws_clone
-            .send_with_str(&serde_json::to_string(&ClientMessage::SessionList {}).unwrap())
-            .unwrap();
-    });
-    ws.set_onopen(Some(open_callback.as_ref().unchecked_ref()));
-    open_callback.forget();
-
     return Ok(());
 }
@@ -0,0 +1,77 @@
+use crate::*;
+
+/// Why a move was rejected.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IllegalMoveReason {
+    Occupied,
+    NoFlips,
+    /// A pass while the colour to move still has a legal put.
+    MustPut,
+    /// Neither colour can move any more.
+    GameOver,
+    /// The move was recorded for the colour that is not on turn.
+    WrongTurn,
+}
+
+impl core::fmt::Display for IllegalMoveReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            IllegalMoveReason::Occupied => write!(f, "the square is occupied"),
+            IllegalMoveReason::NoFlips => write!(f, "no discs would be flipped"),
+            IllegalMoveReason::MustPut => write!(f, "a put is available"),
+            IllegalMoveReason::GameOver => write!(f, "the game is over"),
+            IllegalMoveReason::WrongTurn => write!(f, "it is not that colour's turn"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Format {
+    Notation,
+    Ggf,
+    Wthor,
+}
+
+impl core::fmt::Display for Format {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Format::Notation => write!(f, "square name"),
+            Format::Ggf => write!(f, "GGF"),
+            Format::Wthor => write!(f, "WTHOR"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Error {
+    OutOfRange { x: i32, y: i32 },
+    IllegalMove { mv: Move, reason: IllegalMoveReason },
+    Parse { format: Format, message: String },
+}
+
+impl Error {
+    pub(crate) fn parse(format: Format, message: String) -> Self {
+        Error::Parse { format, message }
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Error::OutOfRange { x, y } => write!(f, "position ({}, {}) is out of range", x, y),
+            Error::IllegalMove {
+                mv: Move::Put(p),
+                reason,
+            } => write!(f, "illegal move {}: {}", p.to_notation(), reason),
+            Error::IllegalMove {
+                mv: Move::Pass,
+                reason,
+            } => write!(f, "illegal pass: {}", reason),
+            Error::Parse { format, message } => write!(f, "invalid {}: {}", format, message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
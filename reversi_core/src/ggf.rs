@@ -0,0 +1,383 @@
+use crate::*;
+use alloc::{string::ToString, vec};
+
+fn syntax(message: String) -> Error {
+    Error::parse(Format::Ggf, message)
+}
+
+/// Evaluation and clock annotations of one recorded move.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct MoveInfo {
+    pub eval: Option<f64>,
+    pub time: Option<String>,
+}
+
+/// A game in Generic Game Format. `moves` has one entry per move of
+/// `record`, including passes that were only implied by the file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GgfGame {
+    pub place: Option<String>,
+    pub date: Option<String>,
+    pub black: Option<String>,
+    pub white: Option<String>,
+    pub black_rating: Option<f64>,
+    pub white_rating: Option<f64>,
+    pub time: Option<String>,
+    pub black_time: Option<String>,
+    pub white_time: Option<String>,
+    pub result: Option<String>,
+    pub record: GameRecord,
+    pub moves: Vec<MoveInfo>,
+}
+
+impl GgfGame {
+    pub fn new(record: GameRecord) -> Self {
+        let moves = vec![MoveInfo::default(); record.moves.len()];
+        Self {
+            place: None,
+            date: None,
+            black: None,
+            white: None,
+            black_rating: None,
+            white_rating: None,
+            time: None,
+            black_time: None,
+            white_time: None,
+            result: None,
+            record,
+            moves,
+        }
+    }
+
+    /// Parses every `(; ... ;)` game in `text`.
+    pub fn parse_all(text: &str) -> Result<Vec<Self>, Error> {
+        let mut games = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("(;") {
+            let end =
+                find_game_end(&rest[start..]).ok_or_else(|| syntax("missing ;)".to_string()))?;
+            games.push(Self::parse(&rest[start..start + end])?);
+            rest = &rest[start + end..];
+        }
+        Ok(games)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let text = text.trim();
+        let body = text
+            .strip_prefix("(;")
+            .and_then(|x| x.strip_suffix(";)"))
+            .ok_or_else(|| syntax("game must be enclosed in (; ;)".to_string()))?;
+        let properties = parse_properties(body)?;
+        let mut game = Self::new(GameRecord::new());
+        let mut moves = Vec::new();
+        for (key, value) in properties {
+            match key.as_str() {
+                "GM" if !value.eq_ignore_ascii_case("othello") => {
+                    return Err(syntax(format!("not an othello game: {}", value)))
+                }
+                "TY" if value.trim() != "8" => {
+                    return Err(syntax(format!("unsupported board type {}", value)))
+                }
+                "PC" => game.place = Some(value),
+                "DT" => game.date = Some(value),
+                "PB" => game.black = Some(value),
+                "PW" => game.white = Some(value),
+                "RB" => game.black_rating = Some(parse_number(&key, &value)?),
+                "RW" => game.white_rating = Some(parse_number(&key, &value)?),
+                "TI" => game.time = Some(value),
+                "TB" => game.black_time = Some(value),
+                "TW" => game.white_time = Some(value),
+                "RE" => game.result = Some(value),
+                "BO" => {
+                    let (field, turn) = parse_board(&value)?;
+                    game.record = GameRecord::from_setup(field, turn);
+                }
+                "B" => moves.push((BorW::Black, value)),
+                "W" => moves.push((BorW::White, value)),
+                _ => {}
+            }
+        }
+        for (color, value) in moves {
+            let (mv, info) = parse_move(&value)?;
+            let (field, turn) = game.record.current();
+            if turn != color && mv != Move::Pass && !field.puttable(turn) {
+                game.record.push(Move::Pass)?;
+                game.moves.push(MoveInfo::default());
+            }
+            let (_, turn) = game.record.current();
+            if turn != color {
+                return Err(Error::IllegalMove {
+                    mv,
+                    reason: IllegalMoveReason::WrongTurn,
+                });
+            }
+            game.record.push(mv)?;
+            game.moves.push(info);
+        }
+        Ok(game)
+    }
+
+    pub fn to_ggf(&self) -> String {
+        let mut out = String::from("(;GM[Othello]");
+        let mut property = |key: &str, value: &Option<String>| {
+            if let Some(x) = value {
+                out += &format!("{}[{}]", key, escape(x));
+            }
+        };
+        property("PC", &self.place);
+        property("DT", &self.date);
+        property("PB", &self.black);
+        property("PW", &self.white);
+        property("RB", &self.black_rating.map(|x| format!("{:.2}", x)));
+        property("RW", &self.white_rating.map(|x| format!("{:.2}", x)));
+        property("TI", &self.time);
+        property("TB", &self.black_time);
+        property("TW", &self.white_time);
+        out += "TY[8]";
+        if let Some(x) = &self.result {
+            out += &format!("RE[{}]", escape(x));
+        }
+        out += &format!(
+            "BO[{}]",
+            format_board(&self.record.start, self.record.first)
+        );
+        let mut turn = self.record.first;
+        for (i, mv) in self.record.moves.iter().enumerate() {
+            let info = self.moves.get(i).cloned().unwrap_or_default();
+            let key = match turn {
+                BorW::Black => "B",
+                BorW::White => "W",
+            };
+            let mut value = match mv {
+                Move::Put(p) => p.to_notation(),
+                Move::Pass => "pa".to_string(),
+            };
+            if info.eval.is_some() || info.time.is_some() {
+                value += &format!(
+                    "/{}/{}",
+                    info.eval.map(|x| format!("{:.2}", x)).unwrap_or_default(),
+                    info.time.unwrap_or_default()
+                );
+            }
+            out += &format!("{}[{}]", key, value);
+            turn = get_another_color(turn);
+        }
+        out += ";)";
+        out
+    }
+}
+
+fn find_game_end(text: &str) -> Option<usize> {
+    let mut in_value = false;
+    let mut escaped = false;
+    let bytes = text.as_bytes();
+    for (i, &c) in bytes.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if c == b'\\' {
+            escaped = true;
+        } else if c == b'[' {
+            in_value = true;
+        } else if c == b']' {
+            in_value = false;
+        } else if !in_value && c == b';' && bytes.get(i + 1) == Some(&b')') && i > 1 {
+            return Some(i + 2);
+        }
+    }
+    None
+}
+
+fn parse_properties(body: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut properties = Vec::new();
+    let mut chars = body.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if !c.is_ascii_alphanumeric() {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        match chars.next() {
+            None if key.is_empty() => return Ok(properties),
+            Some('[') if !key.is_empty() => {}
+            _ => return Err(syntax(format!("expected property after {}", key))),
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                None => return Err(syntax(format!("unterminated {}", key))),
+                Some('\\') => value.extend(chars.next()),
+                Some(']') => break,
+                Some(c) => value.push(c),
+            }
+        }
+        properties.push((key, value));
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(']', "\\]")
+}
+
+fn parse_number(key: &str, value: &str) -> Result<f64, Error> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| syntax(format!("invalid {} value {}", key, value)))
+}
+
+fn parse_move(value: &str) -> Result<(Move, MoveInfo), Error> {
+    let mut parts = value.splitn(3, '/');
+    let square = parts.next().unwrap_or_default().trim();
+    let mv = if square.eq_ignore_ascii_case("pa") {
+        Move::Pass
+    } else {
+        Move::Put(
+            Position::from_notation(square)
+                .map_err(|_| syntax(format!("invalid move {}", square)))?,
+        )
+    };
+    let eval = match parts.next().map(str::trim) {
+        None | Some("") => None,
+        Some(x) => Some(parse_number("eval", x)?),
+    };
+    let time = match parts.next().map(str::trim) {
+        None | Some("") => None,
+        Some(x) => Some(x.to_string()),
+    };
+    Ok((mv, MoveInfo { eval, time }))
+}
+
+fn parse_board(value: &str) -> Result<(Field, BorW), Error> {
+    let mut tokens = value.split_whitespace();
+    let size = tokens.next().unwrap_or_default();
+    if size != "8" {
+        return Err(syntax(format!("unsupported board type {}", size)));
+    }
+    let squares = tokens.collect::<String>();
+    if squares.chars().count() != 65 {
+        return Err(syntax(format!("invalid board {}", value)));
+    }
+    let mut field = Field::new();
+    for (i, c) in squares.chars().take(64).enumerate() {
+        let masu = match c {
+            '-' => Masu::Empty,
+            '*' => Masu::Putted(BorW::Black),
+            'O' => Masu::Putted(BorW::White),
+            _ => return Err(syntax(format!("invalid square {}", c))),
+        };
+        let p =
+            Position::from_notation(&format!("{}{}", (b'a' + (i % 8) as u8) as char, i / 8 + 1))
+                .unwrap();
+        field.set(p, masu);
+    }
+    let turn = match squares.chars().last() {
+        Some('*') => BorW::Black,
+        Some('O') => BorW::White,
+        _ => return Err(syntax(format!("invalid board {}", value))),
+    };
+    Ok((field, turn))
+}
+
+fn format_board(field: &Field, turn: BorW) -> String {
+    let mut out = String::from("8");
+    for rank in 1..=8 {
+        out.push(' ');
+        for file in 'a'..='h' {
+            let p = Position::from_notation(&format!("{}{}", file, rank)).unwrap();
+            out.push(match field.get(p) {
+                Masu::Empty => '-',
+                Masu::Putted(BorW::Black) => '*',
+                Masu::Putted(BorW::White) => 'O',
+            });
+        }
+    }
+    out.push(' ');
+    out.push(match turn {
+        BorW::Black => '*',
+        BorW::White => 'O',
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const GAME: &str = "(;GM[Othello]PC[GGS/os]DT[2003.12.15_13:24:03.MST]PB[foo]PW[bar]RB[2000.00]RW[1800.50]TI[05:00//02:00]TY[8]RE[+64.000]BO[8 -------- -------- -------- ---O*--- ---*O--- -------- -------- -------- *]B[d3//0.01]W[c5/-1.50/0.02];)";
+
+    #[test]
+    fn parse_test() {
+        let game = GgfGame::parse(GAME).unwrap();
+        assert!(game.black.as_deref() == Some("foo"));
+        assert!(game.white_rating == Some(1800.5));
+        assert!(game.record.start == Field::new());
+        assert!(game.record.first == BorW::Black);
+        assert!(game.moves[1].eval == Some(-1.5));
+        assert!(game.moves[0].time.as_deref() == Some("0.01"));
+        assert!(
+            game.record.moves
+                == vec![
+                    Move::Put(Position::from_notation("d3").unwrap()),
+                    Move::Put(Position::from_notation("c5").unwrap()),
+                ]
+        );
+    }
+    #[test]
+    fn roundtrip_test() {
+        let game = GgfGame::parse(GAME).unwrap();
+        let text = game.to_ggf();
+        assert!(GgfGame::parse(&text).unwrap() == game);
+        let games = GgfGame::parse_all(&format!("{}\n{}", text, GAME)).unwrap();
+        assert!(games.len() == 2);
+    }
+    #[test]
+    fn implied_pass_test() {
+        let mut field = Field::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                field.set(Position::new(x, y).unwrap(), Masu::Empty);
+            }
+        }
+        let a8 = Position::from_notation("a8").unwrap();
+        field.set(a8, Masu::Putted(BorW::Black));
+        field.set(
+            Position::from_notation("b8").unwrap(),
+            Masu::Putted(BorW::White),
+        );
+        field.set(
+            Position::from_notation("d8").unwrap(),
+            Masu::Putted(BorW::White),
+        );
+        let mut game = GgfGame::new(GameRecord::from_setup(field, BorW::White));
+        game.record.push(Move::Pass).unwrap();
+        game.moves.push(MoveInfo::default());
+        let text = game.to_ggf().replace("W[pa]", "");
+        let parsed = GgfGame::parse(&format!("{}B[c8];)", &text[..text.len() - 2])).unwrap();
+        assert!(parsed.record.moves[0] == Move::Pass);
+        assert!(parsed.record.moves.len() == 2);
+    }
+    #[test]
+    fn illegal_move_test() {
+        let text = GAME.replace("W[c5", "W[a1");
+        assert!(
+            GgfGame::parse(&text).err()
+                == Some(Error::IllegalMove {
+                    mv: Move::Put(Position::from_notation("a1").unwrap()),
+                    reason: IllegalMoveReason::NoFlips,
+                })
+        );
+        assert!(matches!(
+            GgfGame::parse(&GAME.replace("TY[8]", "TY[10]")),
+            Err(Error::Parse {
+                format: Format::Ggf,
+                ..
+            })
+        ));
+    }
+}
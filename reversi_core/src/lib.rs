@@ -1,14 +1,23 @@
-use serde::{Deserialize, Serialize};
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-#[derive(Debug, Clone)]
-pub struct FieldOutError;
-impl std::fmt::Display for FieldOutError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "invalid range position")
-    }
-}
+extern crate alloc;
+
+mod error;
+mod ggf;
+mod wthor;
+
+use alloc::{format, string::String, vec::Vec};
+pub use error::{Error, Format, IllegalMoveReason};
+pub use ggf::{GgfGame, MoveInfo};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+pub use wthor::{
+    parse_player_names, parse_tournament_names, GameDatabase, NextMoveStats, SearchResult,
+    WthorFile, WthorGame,
+};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ReversiData {
     pub field: Field,
     pub cursor: Position,
@@ -26,7 +35,8 @@ impl ReversiData {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Field {
     field: [[Masu; 8]; 8],
 }
@@ -91,32 +101,33 @@ impl Field {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Position {
     x: usize,
     y: usize,
 }
 
 impl Position {
-    pub fn new(x: i32, y: i32) -> std::result::Result<Self, FieldOutError> {
+    pub fn new(x: i32, y: i32) -> Result<Self, Error> {
         if x < 0 || y < 0 || 7 < x || 7 < y {
-            return Err(FieldOutError);
+            return Err(Error::OutOfRange { x, y });
         }
         Ok(Self {
             x: x as usize,
             y: y as usize,
         })
     }
-    pub fn up(self) -> std::result::Result<Self, FieldOutError> {
+    pub fn up(self) -> Result<Self, Error> {
         Self::new(self.x as i32, self.y as i32 - 1)
     }
-    pub fn down(self) -> std::result::Result<Self, FieldOutError> {
+    pub fn down(self) -> Result<Self, Error> {
         Self::new(self.x as i32, self.y as i32 + 1)
     }
-    pub fn left(self) -> std::result::Result<Self, FieldOutError> {
+    pub fn left(self) -> Result<Self, Error> {
         Self::new(self.x as i32 - 1, self.y as i32)
     }
-    pub fn right(self) -> std::result::Result<Self, FieldOutError> {
+    pub fn right(self) -> Result<Self, Error> {
         Self::new(self.x as i32 + 1, self.y as i32)
     }
     pub fn x(self) -> usize {
@@ -125,15 +136,39 @@ impl Position {
     pub fn y(self) -> usize {
         self.y
     }
+    /// Parses a square name such as "d3". Files a-h run along x and ranks
+    /// 1-8 count up from the bottom row, so that `Field::new()` is the
+    /// standard opening position in this notation.
+    pub fn from_notation(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::parse(Format::Notation, String::from(s));
+        let mut chars = s.chars();
+        let file = chars.next().ok_or_else(invalid)?.to_ascii_lowercase();
+        let rank = chars.next().ok_or_else(invalid)?;
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return Err(invalid());
+        }
+        Self::new(file as i32 - 'a' as i32, '8' as i32 - rank as i32)
+    }
+    pub fn to_notation(self) -> String {
+        format!(
+            "{}{}",
+            (b'a' + self.x as u8) as char,
+            (b'8' - self.y as u8) as char
+        )
+    }
 }
 
-#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Masu {
     Empty,
     Putted(BorW),
 }
 
-#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum BorW {
     Black,
     White,
@@ -206,15 +241,101 @@ pub fn create_initial_data() -> (Field, Position, BorW) {
     return (field, cursor, turn);
 }
 
-pub fn try_put(data: &mut ReversiData) {
-    if check_putable(&data.field, data.cursor, data.turn) {
-        data.field.set(data.cursor, Masu::Putted(data.turn));
-        auto_reverse(&mut data.field, data.cursor, data.turn);
-        data.turn = get_another_color(data.turn);
-        if !data.field.puttable(data.turn) {
-            data.turn = get_another_color(data.turn);
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Move {
+    Put(Position),
+    Pass,
+}
+
+/// A game as its starting position plus the moves played from it. Passes
+/// are explicit, so the colour of every move follows from `first`.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GameRecord {
+    pub start: Field,
+    pub first: BorW,
+    pub moves: Vec<Move>,
+}
+
+impl GameRecord {
+    pub fn new() -> Self {
+        Self::from_setup(Field::new(), BorW::Black)
+    }
+    pub fn from_setup(start: Field, first: BorW) -> Self {
+        Self {
+            start,
+            first,
+            moves: Vec::new(),
+        }
+    }
+    /// The position and the colour to move after all recorded moves.
+    pub fn current(&self) -> (Field, BorW) {
+        let mut field = self.start.clone();
+        let mut turn = self.first;
+        for mv in self.moves.iter() {
+            apply_move(&mut field, *mv, turn);
+            turn = get_another_color(turn);
+        }
+        (field, turn)
+    }
+    /// Appends `mv` after checking it against the current position: a put
+    /// must pass `check_putable` and a pass is only allowed when the colour
+    /// to move has no legal put while the game is still going on.
+    pub fn push(&mut self, mv: Move) -> Result<(), Error> {
+        let (field, turn) = self.current();
+        check_move(&field, mv, turn)?;
+        self.moves.push(mv);
+        Ok(())
+    }
+    /// Plays a put and records the forced pass that follows it, if any, the
+    /// same way `try_put` hands the turn back.
+    pub fn play(&mut self, p: Position) -> Result<(), Error> {
+        self.push(Move::Put(p))?;
+        let (field, turn) = self.current();
+        if !field.puttable(turn) && field.puttable(get_another_color(turn)) {
+            self.moves.push(Move::Pass);
         }
+        Ok(())
+    }
+}
+
+impl Default for GameRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks `mv` for `turn` like `check_putable`, but tells why it is illegal.
+pub fn check_move(field: &Field, mv: Move, turn: BorW) -> Result<(), Error> {
+    let reason = match mv {
+        Move::Put(p) if field.get(p) != Masu::Empty => IllegalMoveReason::Occupied,
+        Move::Put(p) if !check_putable(field, p, turn) => IllegalMoveReason::NoFlips,
+        Move::Put(_) => return Ok(()),
+        Move::Pass if field.puttable(turn) => IllegalMoveReason::MustPut,
+        Move::Pass if !field.puttable(get_another_color(turn)) => IllegalMoveReason::GameOver,
+        Move::Pass => return Ok(()),
+    };
+    Err(Error::IllegalMove { mv, reason })
+}
+
+fn apply_move(field: &mut Field, mv: Move, turn: BorW) {
+    if let Move::Put(p) = mv {
+        field.set(p, Masu::Putted(turn));
+        auto_reverse(field, p, turn);
+    }
+}
+
+pub fn try_put(data: &mut ReversiData) -> Result<(), Error> {
+    check_move(&data.field, Move::Put(data.cursor), data.turn)?;
+    data.field.set(data.cursor, Masu::Putted(data.turn));
+    auto_reverse(&mut data.field, data.cursor, data.turn);
+    data.turn = get_another_color(data.turn);
+    if !data.field.puttable(data.turn) {
+        data.turn = get_another_color(data.turn);
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -250,6 +371,60 @@ mod tests {
         assert!(field.get(Position::new(3, 4).unwrap()) == Masu::Putted(BorW::Black));
     }
     #[test]
+    fn notation_test() {
+        let p = Position::from_notation("d3").unwrap();
+        assert!(p.x() == 3 && p.y() == 5);
+        assert!(p.to_notation() == "d3");
+        assert!(Position::from_notation("A8").unwrap() == Position::new(0, 0).unwrap());
+        assert!(Position::from_notation("i1").is_err());
+        assert!(Position::from_notation("a9").is_err());
+        assert!(Position::from_notation("a1x").is_err());
+        assert!(check_putable(&Field::new(), p, BorW::Black));
+    }
+    #[test]
+    fn game_record_test() {
+        let mut record = GameRecord::new();
+        let d3 = Position::from_notation("d3").unwrap();
+        assert!(record.push(Move::Pass).is_err());
+        assert!(record
+            .push(Move::Put(Position::new(0, 0).unwrap()))
+            .is_err());
+        record.play(d3).unwrap();
+        assert!(record.moves == vec![Move::Put(d3)]);
+        let (field, turn) = record.current();
+        assert!(field.get(d3) == Masu::Putted(BorW::Black));
+        assert!(turn == BorW::White);
+    }
+    #[test]
+    fn error_test() {
+        assert!(Position::new(8, -1).err() == Some(Error::OutOfRange { x: 8, y: -1 }));
+        let mut data = ReversiData::new();
+        data.cursor = Position::new(3, 3).unwrap();
+        let occupied = try_put(&mut data).unwrap_err();
+        assert!(format!("{}", occupied) == "illegal move d5: the square is occupied");
+        data.cursor = Position::new(0, 0).unwrap();
+        assert!(
+            try_put(&mut data).err()
+                == Some(Error::IllegalMove {
+                    mv: Move::Put(data.cursor),
+                    reason: IllegalMoveReason::NoFlips,
+                })
+        );
+        assert!(
+            check_move(&data.field, Move::Pass, BorW::Black).err()
+                == Some(Error::IllegalMove {
+                    mv: Move::Pass,
+                    reason: IllegalMoveReason::MustPut,
+                })
+        );
+        #[cfg(feature = "std")]
+        {
+            let boxed: Box<dyn std::error::Error> =
+                Box::new(Position::from_notation("z9").unwrap_err());
+            assert!(boxed.to_string() == "invalid square name: z9");
+        }
+    }
+    #[test]
     fn count_test() {
         let mut field = Field::new();
         field.set(Position::new(0, 0).unwrap(), Masu::Putted(BorW::Black));
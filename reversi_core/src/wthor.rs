@@ -0,0 +1,348 @@
+use crate::*;
+
+const HEADER_SIZE: usize = 16;
+const GAME_SIZE: usize = 68;
+const PLAYER_SIZE: usize = 20;
+const TOURNAMENT_SIZE: usize = 26;
+
+fn truncated() -> Error {
+    Error::parse(Format::Wthor, String::from("file is truncated"))
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct WthorGame {
+    pub tournament: u16,
+    pub black: u16,
+    pub white: u16,
+    /// Black discs at the end of the game, empties counted for the winner.
+    pub black_score: u8,
+    pub theoretical_score: u8,
+    pub record: GameRecord,
+}
+
+impl WthorGame {
+    pub fn result(&self) -> GameResult {
+        match self.black_score {
+            32 => GameResult::Draw,
+            x if x > 32 => GameResult::Win(BorW::Black),
+            _ => GameResult::Win(BorW::White),
+        }
+    }
+}
+
+/// Header and games of a WTHOR `.wtb` game file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WthorFile {
+    pub created: (u16, u8, u8),
+    pub year: u16,
+    pub depth: u8,
+    pub games: Vec<WthorGame>,
+}
+
+impl WthorFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let header = bytes.get(..HEADER_SIZE).ok_or_else(truncated)?;
+        let count = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if header[12] != 0 && header[12] != 8 {
+            return Err(Error::parse(
+                Format::Wthor,
+                format!("unsupported board size {}", header[12]),
+            ));
+        }
+        let games = bytes[HEADER_SIZE..]
+            .chunks(GAME_SIZE)
+            .take(count)
+            .enumerate()
+            .map(|(i, chunk)| parse_game(i, chunk))
+            .collect::<Result<Vec<WthorGame>, Error>>()?;
+        if games.len() < count {
+            return Err(truncated());
+        }
+        Ok(Self {
+            created: (
+                header[0] as u16 * 100 + header[1] as u16,
+                header[2],
+                header[3],
+            ),
+            year: u16::from_le_bytes([header[10], header[11]]),
+            depth: header[14],
+            games,
+        })
+    }
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Names from a `WTHOR.JOU` player file, indexed by player number.
+pub fn parse_player_names(bytes: &[u8]) -> Result<Vec<String>, Error> {
+    parse_names(bytes, PLAYER_SIZE)
+}
+
+/// Names from a `WTHOR.TRN` tournament file, indexed by tournament number.
+pub fn parse_tournament_names(bytes: &[u8]) -> Result<Vec<String>, Error> {
+    parse_names(bytes, TOURNAMENT_SIZE)
+}
+
+fn parse_names(bytes: &[u8], size: usize) -> Result<Vec<String>, Error> {
+    let header = bytes.get(..HEADER_SIZE).ok_or_else(truncated)?;
+    let count = u16::from_le_bytes([header[8], header[9]]) as usize;
+    let body = bytes
+        .get(HEADER_SIZE..HEADER_SIZE + count * size)
+        .ok_or_else(truncated)?;
+    Ok(body
+        .chunks(size)
+        .map(|x| {
+            let end = x.iter().position(|&c| c == 0).unwrap_or(x.len());
+            x[..end].iter().map(|&c| c as char).collect()
+        })
+        .collect())
+}
+
+fn parse_game(index: usize, chunk: &[u8]) -> Result<WthorGame, Error> {
+    if chunk.len() < GAME_SIZE {
+        return Err(truncated());
+    }
+    let mut record = GameRecord::new();
+    for &value in chunk[8..].iter().take_while(|&&x| x != 0) {
+        let (row, column) = (value / 10, value % 10);
+        if !(1..=8).contains(&row) || !(1..=8).contains(&column) {
+            return Err(Error::parse(
+                Format::Wthor,
+                format!("game {}: invalid square {}", index + 1, value),
+            ));
+        }
+        let p = Position::new(column as i32 - 1, 8 - row as i32).unwrap();
+        let (field, turn) = record.current();
+        let in_game = |e: Error| Error::parse(Format::Wthor, format!("game {}: {}", index + 1, e));
+        if !check_putable(&field, p, turn) && !field.puttable(turn) {
+            record.push(Move::Pass).map_err(in_game)?;
+        }
+        record.push(Move::Put(p)).map_err(in_game)?;
+    }
+    Ok(WthorGame {
+        tournament: u16::from_le_bytes([chunk[0], chunk[1]]),
+        black: u16::from_le_bytes([chunk[2], chunk[3]]),
+        white: u16::from_le_bytes([chunk[4], chunk[5]]),
+        black_score: chunk[6],
+        theoretical_score: chunk[7],
+        record,
+    })
+}
+
+fn transform(p: Position, symmetry: usize) -> Position {
+    let (x, y) = (p.x() as i32, p.y() as i32);
+    let (x, y) = match symmetry {
+        0 => (x, y),
+        1 => (7 - x, y),
+        2 => (x, 7 - y),
+        3 => (7 - x, 7 - y),
+        4 => (y, x),
+        5 => (7 - y, x),
+        6 => (y, 7 - x),
+        _ => (7 - y, 7 - x),
+    };
+    Position::new(x, y).unwrap()
+}
+
+fn inverse(symmetry: usize) -> usize {
+    match symmetry {
+        5 => 6,
+        6 => 5,
+        x => x,
+    }
+}
+
+fn transform_field(field: &Field, symmetry: usize) -> Field {
+    let mut result = field.clone();
+    for x in 0..8 {
+        for y in 0..8 {
+            let p = Position::new(x, y).unwrap();
+            result.set(transform(p, symmetry), field.get(p));
+        }
+    }
+    result
+}
+
+/// Win statistics of one continuation, as seen from the searched position.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NextMoveStats {
+    pub mv: Option<Move>,
+    pub games: usize,
+    pub black_wins: usize,
+    pub white_wins: usize,
+    pub draws: usize,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SearchResult {
+    /// Indexes into `GameDatabase::games` of every game reaching the position.
+    pub games: Vec<usize>,
+    /// One entry per continuation; `mv` is `None` for games that ended there.
+    pub next_moves: Vec<NextMoveStats>,
+}
+
+pub struct GameDatabase {
+    pub games: Vec<WthorGame>,
+}
+
+impl GameDatabase {
+    pub fn new(games: Vec<WthorGame>) -> Self {
+        Self { games }
+    }
+    /// Finds every game passing through `field` with `turn` to move, or
+    /// through any of its rotations and reflections. Next moves are mapped
+    /// back onto the orientation of `field`.
+    pub fn search(&self, field: &Field, turn: BorW) -> SearchResult {
+        let twins = (0..8)
+            .map(|s| transform_field(field, s))
+            .collect::<Vec<Field>>();
+        let puts = field.count(BorW::Black) + field.count(BorW::White);
+        let mut result = SearchResult {
+            games: Vec::new(),
+            next_moves: Vec::new(),
+        };
+        for (index, game) in self.games.iter().enumerate() {
+            let record = &game.record;
+            let mut current = record.start.clone();
+            let mut current_turn = record.first;
+            let mut found = None;
+            for (ply, mv) in record.moves.iter().enumerate() {
+                if current.count(BorW::Black) + current.count(BorW::White) > puts {
+                    break;
+                }
+                if current_turn == turn {
+                    if let Some(s) = twins.iter().position(|x| *x == current) {
+                        found = Some((s, Some(*mv)));
+                        break;
+                    }
+                }
+                apply_move(&mut current, *mv, current_turn);
+                current_turn = get_another_color(current_turn);
+                if ply + 1 == record.moves.len() && current_turn == turn {
+                    if let Some(s) = twins.iter().position(|x| *x == current) {
+                        found = Some((s, None));
+                    }
+                }
+            }
+            let (symmetry, mv) = match found {
+                Some(x) => x,
+                None => continue,
+            };
+            let mv = mv.map(|x| match x {
+                Move::Put(p) => Move::Put(transform(p, inverse(symmetry))),
+                Move::Pass => Move::Pass,
+            });
+            result.games.push(index);
+            let stats = match result.next_moves.iter_mut().find(|x| x.mv == mv) {
+                Some(x) => x,
+                None => {
+                    result.next_moves.push(NextMoveStats {
+                        mv,
+                        games: 0,
+                        black_wins: 0,
+                        white_wins: 0,
+                        draws: 0,
+                    });
+                    result.next_moves.last_mut().unwrap()
+                }
+            };
+            stats.games += 1;
+            match game.result() {
+                GameResult::Win(BorW::Black) => stats.black_wins += 1,
+                GameResult::Win(BorW::White) => stats.white_wins += 1,
+                _ => stats.draws += 1,
+            }
+        }
+        result
+            .next_moves
+            .sort_by_key(|x| core::cmp::Reverse(x.games));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> u8 {
+        let p = Position::from_notation(name).unwrap();
+        (8 - p.y() as u8) * 10 + p.x() as u8 + 1
+    }
+
+    fn wthor_bytes(games: &[(&[&str], u8)]) -> Vec<u8> {
+        let mut bytes = vec![20, 22, 1, 2];
+        bytes.extend((games.len() as u32).to_le_bytes());
+        bytes.extend([0, 0, 0xe6, 0x07, 8, 0, 22, 0]);
+        for (moves, score) in games {
+            bytes.extend([1, 0, 2, 0, 3, 0, *score, *score]);
+            let mut squares = moves.iter().map(|x| square(x)).collect::<Vec<u8>>();
+            squares.resize(60, 0);
+            bytes.extend(squares);
+        }
+        bytes
+    }
+
+    #[test]
+    fn parse_test() {
+        let bytes = wthor_bytes(&[(&["f5", "d6", "c3"], 40)]);
+        let file = WthorFile::parse(&bytes).unwrap();
+        assert!(file.created == (2022, 1, 2));
+        assert!(file.year == 2022);
+        assert!(file.games.len() == 1);
+        let game = &file.games[0];
+        assert!(game.black == 2 && game.white == 3);
+        assert!(game.record.moves[0] == Move::Put(Position::from_notation("f5").unwrap()));
+        assert!(game.record.moves.len() == 3);
+        assert!(WthorFile::parse(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad = bytes.clone();
+        bad[HEADER_SIZE + 9] = square("a1");
+        assert!(
+            WthorFile::parse(&bad).unwrap_err().to_string()
+                == "invalid WTHOR: game 1: illegal move a1: no discs would be flipped"
+        );
+    }
+
+    #[test]
+    fn names_test() {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[8] = 2;
+        let mut name = b"Tamenori".to_vec();
+        name.resize(PLAYER_SIZE, 0);
+        bytes.extend(&name);
+        bytes.extend(&name);
+        assert!(parse_player_names(&bytes).unwrap() == vec!["Tamenori", "Tamenori"]);
+        assert!(parse_tournament_names(&bytes).is_err());
+    }
+
+    #[test]
+    fn search_test() {
+        // d3 and f5 are mirror openings of each other.
+        let bytes = wthor_bytes(&[
+            (&["f5", "d6"], 40),
+            (&["d3", "c5"], 20),
+            (&["f5", "f6"], 32),
+            (&["c4"], 33),
+        ]);
+        let database = GameDatabase::new(WthorFile::parse(&bytes).unwrap().games);
+        let field = database.games[0].record.current().0;
+        let mut after_f5 = Field::new();
+        apply_move(
+            &mut after_f5,
+            Move::Put(Position::from_notation("f5").unwrap()),
+            BorW::Black,
+        );
+        let result = database.search(&after_f5, BorW::White);
+        assert!(result.games == vec![0, 1, 2, 3]);
+        let d6 = Move::Put(Position::from_notation("d6").unwrap());
+        let stats = result.next_moves.iter().find(|x| x.mv == Some(d6)).unwrap();
+        assert!(stats.games == 2);
+        assert!(stats.black_wins == 1 && stats.white_wins == 1);
+        assert!(database.search(&field, BorW::Black).next_moves[0]
+            .mv
+            .is_none());
+        assert!(database.search(&Field::new(), BorW::Black).games.len() == 4);
+    }
+}
@@ -24,7 +24,7 @@ fn input(event: web_sys::KeyboardEvent, data: &mut ReversiData) {
             data.cursor = data.cursor.right().unwrap_or(data.cursor);
         }
         "Enter" => {
-            try_put(data);
+            let _ = try_put(data);
         }
         _ => {}
     }
@@ -53,7 +53,7 @@ fn main() -> Result<(), JsValue> {
     let closure2 = Closure::wrap(Box::new(move |e: web_sys::MouseEvent| {
         if let Ok(x) = mouseinput(e) {
             data.borrow_mut().cursor = x;
-            try_put(&mut data.borrow_mut());
+            let _ = try_put(&mut data.borrow_mut());
         }
         view(&data.borrow(), &canvas2);
     }) as Box<dyn FnMut(_)>);
@@ -1,26 +1,587 @@
-use reversi_core::{Position, ReversiData};
-use serde::{Deserialize, Serialize};
-#[derive(Serialize, Deserialize)]
+//! Messages between reversi_server and its clients, sent as JSON text or in
+//! one of the binary `Encoding`s agreed on in `Hello`.
+//!
+//! Messages are tagged with their snake_case name under `type`, with any
+//! content under `body`, so adding a variant never changes how the others
+//! look. Every other enum goes by snake_case names as well, and the ones a
+//! newer server may extend read unknown names as their `Unknown` variant. Fields added to an existing message get `#[serde(default)]` so
+//! that the other side can leave them out. Anything older clients cannot
+//! handle bumps `PROTOCOL_VERSION`.
+
+use reversi_core::{BorW, Position, ReversiData};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+use std::fmt;
+use uuid::Uuid;
+
+/// Version of the messages below, sent in `Hello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "body", rename_all = "snake_case")]
 pub enum ClientMessage {
+    /// Has to be the first message on a connection.
+    Hello {
+        protocol_version: u32,
+        client_name: String,
+        /// Encodings the client can read, most preferred first.
+        #[serde(default)]
+        encodings: Vec<Encoding>,
+    },
     SessionList,
-    CreateGame,
+    CreateGame(GameOptions),
     SelectGame(GameID),
+    /// Chooses the name other players see.
+    Login {
+        nickname: String,
+    },
+    /// Takes back the session of a dropped connection, seat included.
+    Resume(SessionToken),
+    /// Queues for a game against someone of similar rating.
+    FindMatch,
+    CancelMatch,
+    Leaderboard,
     Put(Position),
+    /// Asks for a fresh `View` of the current game, after a gap in the
+    /// sequence numbers of `MoveApplied`.
+    Resync,
+    /// Same as `Offer(Proposal::Reset)`.
     Reset,
+    Resign,
+    /// Asks the opponent to agree. Offering what the opponent has already
+    /// offered accepts it.
+    Offer(Proposal),
+    Accept(Proposal),
+    Decline(Proposal),
+    /// Sent to the current game, or to the lobby when not in a game.
+    Chat(String),
+}
+
+impl ClientMessage {
+    /// The `type` tag of the message, for logs and metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientMessage::Hello { .. } => "hello",
+            ClientMessage::SessionList => "session_list",
+            ClientMessage::CreateGame(_) => "create_game",
+            ClientMessage::SelectGame(_) => "select_game",
+            ClientMessage::Login { .. } => "login",
+            ClientMessage::Resume(_) => "resume",
+            ClientMessage::FindMatch => "find_match",
+            ClientMessage::CancelMatch => "cancel_match",
+            ClientMessage::Leaderboard => "leaderboard",
+            ClientMessage::Put(_) => "put",
+            ClientMessage::Resync => "resync",
+            ClientMessage::Reset => "reset",
+            ClientMessage::Resign => "resign",
+            ClientMessage::Offer(_) => "offer",
+            ClientMessage::Accept(_) => "accept",
+            ClientMessage::Decline(_) => "decline",
+            ClientMessage::Chat(_) => "chat",
+        }
+    }
+}
+
+/// Something both players of a game have to agree on.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Proposal {
+    Draw,
+    /// Undoes the proposer's last move and any reply to it.
+    Takeback,
+    /// A new game between the same players with colours swapped.
+    Rematch,
+    /// Starts the game over from the initial position.
+    Reset,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Opponent {
+    /// Whoever joins the game next.
+    #[default]
+    Human,
+    /// The server plays White at the given level.
+    Computer { level: u8 },
+}
+
+/// Thinking time allowed to each player. All times are in milliseconds.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeControl {
+    #[default]
+    Unlimited,
+    /// The whole game has to be played within `main_ms`.
+    SuddenDeath { main_ms: u64 },
+    /// `increment_ms` is added after every move.
+    Fischer { main_ms: u64, increment_ms: u64 },
+    /// After the main time each move has to be made within a period; a
+    /// period that runs out is lost, and the flag falls with the last one.
+    ByoYomi {
+        main_ms: u64,
+        period_ms: u64,
+        periods: u32,
+    },
 }
 
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GameOptions {
+    pub opponent: Opponent,
+    pub time_control: TimeControl,
+}
+
+/// Strongest computer level; level 1 plays at random.
+pub const MAX_COMPUTER_LEVEL: u8 = 4;
+
+/// Longest chat text the server accepts, in characters.
+pub const MAX_CHAT_LENGTH: usize = 500;
+
+/// Longest nickname the server accepts, in characters.
+pub const MAX_NICKNAME_LENGTH: usize = 20;
+
+/// Identifies a logged in player across connections.
+#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub struct SessionToken(pub Uuid);
+
 #[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct GameID(pub u32);
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GameState {
+    /// A seat is still free and no clock has started.
+    #[default]
+    Waiting,
+    Playing,
+    Finished,
+    /// Everyone left before the game was over.
+    Abandoned,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GameSummary {
     pub id: GameID,
+    #[serde(default)]
+    pub state: GameState,
     pub members: u32,
     pub your: bool,
+    #[serde(default)]
+    pub players: Players,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct RatingChange {
+    pub nickname: String,
+    pub old: i32,
+    pub new: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct LeaderboardEntry {
+    pub rank: u32,
+    pub nickname: String,
+    pub rating: i32,
+}
+
+/// Nicknames of everyone in a game.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Players {
+    pub black: Option<String>,
+    pub white: Option<String>,
+    pub spectators: Vec<String>,
+}
+
+/// Both clocks of a timed game as of the message that carries them.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ClockState {
+    /// Main time left, not counting the running side's current move.
+    pub black_ms: u64,
+    pub white_ms: u64,
+    /// Byo-yomi periods left.
+    pub black_periods: u32,
+    pub white_periods: u32,
+    /// Length of a byo-yomi period, 0 without byo-yomi.
+    pub period_ms: u64,
+    pub running: Option<BorW>,
+    /// How long the running side had been thinking when this was sent.
+    pub elapsed_ms: u64,
+}
+
+impl ClockState {
+    /// Time left for the current move of `color` and the byo-yomi periods
+    /// left, `since_ms` after this state was sent. `None` once the flag has
+    /// fallen.
+    pub fn left(&self, color: BorW, since_ms: u64) -> Option<(u64, u32)> {
+        let (main, periods) = match color {
+            BorW::Black => (self.black_ms, self.black_periods),
+            BorW::White => (self.white_ms, self.white_periods),
+        };
+        let elapsed = match self.running {
+            Some(x) if x == color => self.elapsed_ms + since_ms,
+            _ => 0,
+        };
+        if elapsed < main || (elapsed == main && periods == 0) {
+            return Some((main - elapsed, periods));
+        }
+        if self.period_ms == 0 {
+            return None;
+        }
+        let over = elapsed - main;
+        let used = (over / self.period_ms) as u32;
+        (used < periods).then(|| (self.period_ms - over % self.period_ms, periods - used))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// Neither side can move.
+    Board,
+    /// The loser's flag fell.
+    Time,
+    Resignation,
+    /// A draw offer was accepted.
+    Agreement,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Player(BorW),
+    Spectator,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    UnknownGame,
+    NotInGame,
+    NotYourTurn,
+    IllegalMove,
+    TooManyGames,
+    MalformedMessage,
+    ChatTooLong,
+    InvalidNickname,
+    NicknameTaken,
+    UnknownSession,
+    InvalidOptions,
+    /// Only the two seated players can do this.
+    NotSeated,
+    /// The opponent has not offered what was accepted or declined.
+    NoOffer,
+    /// The proposal does not fit the state of the game.
+    NotAllowed,
+    /// A code added after this version of the protocol.
+    #[serde(other)]
+    Unknown,
+}
+
+/// What the server offers on top of moves and the game list.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    Sessions,
+    Chat,
+    Matchmaking,
+    Ratings,
+    Computer,
+    Clocks,
+    Negotiation,
+    Deltas,
+    /// A feature added after this version of the protocol.
+    #[serde(other)]
+    Unknown,
+}
+
+/// How a message is turned into the payload of a WebSocket frame.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// Sent in text frames; easiest to read when debugging.
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+/// A message that could not be encoded or decoded.
+#[derive(Debug)]
+pub struct CodecError(pub String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+fn codec_error(e: impl fmt::Display) -> CodecError {
+    CodecError(e.to_string())
+}
+
+impl Encoding {
+    /// Whether messages go in binary rather than text frames.
+    pub fn is_binary(self) -> bool {
+        self != Encoding::Json
+    }
+    pub fn encode<T: Serialize>(self, message: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Encoding::Json => serde_json::to_vec(message).map_err(codec_error),
+            // Structs go as maps so that `#[serde(default)]` fields work.
+            Encoding::MessagePack => rmp_serde::to_vec_named(message).map_err(codec_error),
+            Encoding::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(message, &mut bytes).map_err(codec_error)?;
+                Ok(bytes)
+            }
+        }
+    }
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(codec_error),
+            Encoding::MessagePack => rmp_serde::from_slice(bytes).map_err(codec_error),
+            Encoding::Cbor => ciborium::de::from_reader(bytes).map_err(codec_error),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "body", rename_all = "snake_case")]
 pub enum ServerMessage {
+    /// Reply to a `Hello` the server accepts.
+    Hello {
+        protocol_version: u32,
+        features: Vec<Feature>,
+        /// Encoding of the server's binary frames from here on. Text frames
+        /// are always JSON.
+        #[serde(default)]
+        encoding: Encoding,
+    },
+    /// Reply to a `Hello` the server cannot serve, or to anything sent
+    /// before `Hello`. The server closes the connection afterwards.
+    Rejected {
+        protocol_version: u32,
+        reason: String,
+    },
     GameList(Vec<GameSummary>),
-    View(ReversiData),
+    /// The whole game, sent on joining, on `Resync` and whenever the game
+    /// changes other than by a move. `seq` is the sequence number the next
+    /// `MoveApplied` builds on. `clock` is `None` for games without a time
+    /// limit.
+    View {
+        seq: u64,
+        data: ReversiData,
+        clock: Option<ClockState>,
+    },
+    /// A move by `color`, following the update numbered `seq - 1`. `turn` is
+    /// the colour to move next, which stays the same after a forced pass.
+    MoveApplied {
+        seq: u64,
+        color: BorW,
+        position: Position,
+        flipped: Vec<Position>,
+        turn: BorW,
+        clock: Option<ClockState>,
+    },
+    Role(Role),
+    /// Reply to `Login`.
+    Welcome {
+        nickname: String,
+        token: SessionToken,
+    },
+    /// Sent to everyone in a game whenever someone joins or leaves it.
+    Players(Players),
+    /// Reply to `FindMatch`.
+    Searching,
+    /// Reply to `CancelMatch`.
+    SearchCancelled,
+    /// The search is over; `Role` and `View` for the new game follow.
+    MatchFound {
+        game_id: GameID,
+        color: BorW,
+        opponent: String,
+    },
+    /// `winner` is `None` for a draw. `rating_changes` lists Black and then
+    /// White, and is empty for unrated games.
+    GameOver {
+        winner: Option<BorW>,
+        reason: EndReason,
+        #[serde(default)]
+        rating_changes: Vec<RatingChange>,
+    },
+    /// Sent to everyone in the game when a seated player makes an offer.
+    Offered {
+        proposal: Proposal,
+        by: BorW,
+    },
+    Declined {
+        proposal: Proposal,
+        by: BorW,
+    },
+    /// Reply to `Leaderboard`; `you` is the requester if they are rated.
+    Leaderboard {
+        top: Vec<LeaderboardEntry>,
+        you: Option<LeaderboardEntry>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+    /// `timestamp` is in milliseconds since the Unix epoch.
+    ChatMessage {
+        from: String,
+        text: String,
+        timestamp: u64,
+    },
+    /// A message added after this version of the protocol, whatever its
+    /// body. Clients ignore it; the server never sends it.
+    #[serde(untagged, skip_serializing)]
+    Unknown(IgnoredAny),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn json_test() {
+        let hello = ClientMessage::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: "test".to_string(),
+            encodings: vec![Encoding::Cbor],
+        };
+        assert!(
+            serde_json::to_string(&hello).unwrap()
+                == r#"{"type":"hello","body":{"protocol_version":1,"client_name":"test","encodings":["cbor"]}}"#
+        );
+        let old_hello = r#"{"type":"hello","body":{"protocol_version":1,"client_name":"old"}}"#;
+        assert!(matches!(
+            serde_json::from_str(old_hello).unwrap(),
+            ClientMessage::Hello { encodings, .. } if encodings.is_empty()
+        ));
+        let list = serde_json::to_string(&ClientMessage::SessionList).unwrap();
+        assert!(list == r#"{"type":"session_list"}"#);
+        let options = r#"{"type":"create_game","body":{}}"#;
+        assert!(matches!(
+            serde_json::from_str(options).unwrap(),
+            ClientMessage::CreateGame(GameOptions {
+                opponent: Opponent::Human,
+                time_control: TimeControl::Unlimited,
+            })
+        ));
+        let error = r#"{"type":"error","body":{"code":"something_new","message":""}}"#;
+        assert!(matches!(
+            serde_json::from_str(error).unwrap(),
+            ServerMessage::Error {
+                code: ErrorCode::Unknown,
+                ..
+            }
+        ));
+        let error = r#"{"type":"error","body":{"code":"not_your_turn","message":""}}"#;
+        assert!(matches!(
+            serde_json::from_str(error).unwrap(),
+            ServerMessage::Error {
+                code: ErrorCode::NotYourTurn,
+                ..
+            }
+        ));
+        for new in [
+            r#"{"type":"teleported","body":{"to":[1,2]}}"#,
+            r#"{"type":"teleported","body":3}"#,
+            r#"{"type":"teleported"}"#,
+        ] {
+            assert!(matches!(
+                serde_json::from_str(new).unwrap(),
+                ServerMessage::Unknown(_)
+            ));
+        }
+        let features = serde_json::from_str::<Vec<Feature>>(r#"["chat","teleport"]"#).unwrap();
+        assert!(features == [Feature::Chat, Feature::Unknown]);
+        let role = ServerMessage::Role(Role::Player(BorW::White));
+        assert!(
+            serde_json::to_string(&role).unwrap() == r#"{"type":"role","body":{"player":"white"}}"#
+        );
+        for message in [
+            hello,
+            ClientMessage::FindMatch,
+            ClientMessage::Offer(Proposal::Draw),
+        ] {
+            let json = serde_json::to_value(&message).unwrap();
+            assert!(json["type"] == message.kind());
+        }
+    }
+    #[test]
+    fn encoding_test() {
+        let mut data = ReversiData::new();
+        data.cursor = Position::new(4, 2).unwrap();
+        reversi_core::try_put(&mut data).unwrap();
+        let server_messages = [
+            ServerMessage::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                features: vec![Feature::Chat, Feature::Deltas],
+                encoding: Encoding::Cbor,
+            },
+            ServerMessage::View {
+                seq: 3,
+                data,
+                clock: None,
+            },
+            ServerMessage::MoveApplied {
+                seq: u64::MAX,
+                color: BorW::Black,
+                position: Position::new(2, 3).unwrap(),
+                flipped: vec![Position::new(3, 3).unwrap()],
+                turn: BorW::White,
+                clock: Some(ClockState {
+                    black_ms: 1_000,
+                    white_ms: 2_000,
+                    black_periods: 1,
+                    white_periods: 0,
+                    period_ms: 500,
+                    running: Some(BorW::White),
+                    elapsed_ms: 10,
+                }),
+            },
+            ServerMessage::GameOver {
+                winner: None,
+                reason: EndReason::Agreement,
+                rating_changes: vec![],
+            },
+            ServerMessage::SearchCancelled,
+        ];
+        let client_messages = [
+            ClientMessage::CreateGame(GameOptions {
+                opponent: Opponent::Computer { level: 2 },
+                time_control: TimeControl::Fischer {
+                    main_ms: 60_000,
+                    increment_ms: 1_000,
+                },
+            }),
+            ClientMessage::Offer(Proposal::Takeback),
+            ClientMessage::Chat("こんにちは".to_string()),
+            ClientMessage::Resign,
+        ];
+        // The messages have no `PartialEq`, so compare them as JSON.
+        fn json<T: Serialize>(message: &T) -> String {
+            serde_json::to_string(message).unwrap()
+        }
+        for encoding in [Encoding::Json, Encoding::MessagePack, Encoding::Cbor] {
+            for message in &server_messages {
+                let bytes = encoding.encode(message).unwrap();
+                let decoded = encoding.decode::<ServerMessage>(&bytes).unwrap();
+                assert!(json(message) == json(&decoded));
+            }
+            for message in &client_messages {
+                let bytes = encoding.encode(message).unwrap();
+                let decoded = encoding.decode::<ClientMessage>(&bytes).unwrap();
+                assert!(json(message) == json(&decoded));
+            }
+            assert!(encoding
+                .decode::<ServerMessage>(&[0xc1, 0xff, 0x00])
+                .is_err());
+        }
+    }
 }
@@ -0,0 +1,160 @@
+use super::*;
+use ratings::end_game;
+
+fn index(color: BorW) -> usize {
+    match color {
+        BorW::Black => 0,
+        BorW::White => 1,
+    }
+}
+
+/// Both players' time under one time control.
+pub struct Clock {
+    control: TimeControl,
+    /// Main time left, by colour.
+    main: [Duration; 2],
+    /// Byo-yomi periods left, by colour.
+    periods: [u32; 2],
+    /// The colour whose time is running and since when.
+    running: Option<(BorW, Instant)>,
+}
+
+impl Clock {
+    /// `None` for games without a time limit.
+    pub fn new(control: TimeControl) -> Option<Self> {
+        let (main, periods) = match control {
+            TimeControl::Unlimited => return None,
+            TimeControl::SuddenDeath { main_ms } => (main_ms, 0),
+            TimeControl::Fischer { main_ms, .. } => (main_ms, 0),
+            TimeControl::ByoYomi {
+                main_ms, periods, ..
+            } => (main_ms, periods),
+        };
+        Some(Clock {
+            control,
+            main: [Duration::from_millis(main); 2],
+            periods: [periods; 2],
+            running: None,
+        })
+    }
+    fn period(&self) -> Duration {
+        match self.control {
+            TimeControl::ByoYomi { period_ms, .. } => Duration::from_millis(period_ms),
+            _ => Duration::ZERO,
+        }
+    }
+    fn increment(&self) -> Duration {
+        match self.control {
+            TimeControl::Fischer { increment_ms, .. } => Duration::from_millis(increment_ms),
+            _ => Duration::ZERO,
+        }
+    }
+    /// Main time and periods `color` has left after thinking for `elapsed`,
+    /// or `None` if that is more than they had.
+    fn left_after(&self, color: BorW, elapsed: Duration) -> Option<(Duration, u32)> {
+        let (main, periods) = (self.main[index(color)], self.periods[index(color)]);
+        if elapsed <= main {
+            return Some((main - elapsed, periods));
+        }
+        let period = self.period();
+        if period.is_zero() {
+            return None;
+        }
+        // A period that is not used up starts afresh with the next move.
+        let used = ((elapsed - main).as_nanos() / period.as_nanos()) as u32;
+        (used < periods).then(|| (Duration::ZERO, periods - used))
+    }
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+    /// The colour whose flag has fallen by `now`, if any.
+    pub fn flag_fallen(&self, now: Instant) -> Option<BorW> {
+        let (color, since) = self.running?;
+        self.left_after(color, now.saturating_duration_since(since))
+            .is_none()
+            .then_some(color)
+    }
+    /// Charges the running colour for its move and starts the clock of
+    /// `next`, or stops the clock when `next` is `None`.
+    pub fn punch(&mut self, next: Option<BorW>, now: Instant) {
+        if let Some((color, since)) = self.running {
+            if let Some((main, periods)) =
+                self.left_after(color, now.saturating_duration_since(since))
+            {
+                self.main[index(color)] = main + self.increment();
+                self.periods[index(color)] = periods;
+            }
+        }
+        self.running = next.map(|x| (x, now));
+    }
+    pub fn state(&self, now: Instant) -> ClockState {
+        ClockState {
+            black_ms: self.main[0].as_millis() as u64,
+            white_ms: self.main[1].as_millis() as u64,
+            black_periods: self.periods[0],
+            white_periods: self.periods[1],
+            period_ms: self.period().as_millis() as u64,
+            running: self.running.map(|x| x.0),
+            elapsed_ms: self
+                .running
+                .map_or(0, |x| now.saturating_duration_since(x.1).as_millis() as u64),
+        }
+    }
+}
+
+/// Ends every game whose running clock has run out by `now`, the other
+/// colour winning on time.
+pub fn check_flags(server_data: &mut ServerData, now: Instant) {
+    let fallen = server_data
+        .games
+        .iter()
+        .filter(|x| x.1.state == GameState::Playing)
+        .filter_map(|(id, game)| Some((*id, game.clock.as_ref()?.flag_fallen(now)?)))
+        .collect::<Vec<(GameID, BorW)>>();
+    for (game_id, color) in fallen {
+        info!(game_id = game_id.0, ?color, "lost on time");
+        end_game(
+            server_data,
+            game_id,
+            Some(get_another_color(color)),
+            EndReason::Time,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn clock_test() {
+        let start = Instant::now();
+        let secs = |x| start + Duration::from_secs(x);
+        let mut clock = Clock::new(TimeControl::Fischer {
+            main_ms: 60_000,
+            increment_ms: 5_000,
+        })
+        .unwrap();
+        clock.punch(Some(BorW::Black), start);
+        clock.punch(Some(BorW::White), secs(10));
+        let state = clock.state(secs(10));
+        assert!(state.black_ms == 55_000 && state.white_ms == 60_000);
+        assert!(clock.flag_fallen(secs(70)).is_none());
+        assert!(clock.flag_fallen(secs(71)) == Some(BorW::White));
+        let mut clock = Clock::new(TimeControl::ByoYomi {
+            main_ms: 10_000,
+            period_ms: 5_000,
+            periods: 2,
+        })
+        .unwrap();
+        clock.punch(Some(BorW::Black), start);
+        clock.punch(Some(BorW::White), secs(17));
+        let state = clock.state(secs(17));
+        assert!(state.black_ms == 0 && state.black_periods == 1);
+        assert!(state.left(BorW::White, 12_000) == Some((3_000, 2)));
+        assert!(state.left(BorW::White, 19_000) == Some((1_000, 1)));
+        clock.punch(Some(BorW::Black), secs(18));
+        assert!(clock.flag_fallen(secs(22)).is_none());
+        assert!(clock.flag_fallen(secs(24)) == Some(BorW::Black));
+        assert!(Clock::new(TimeControl::Unlimited).is_none());
+    }
+}
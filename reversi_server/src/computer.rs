@@ -0,0 +1,132 @@
+use super::*;
+use futures_channel::mpsc::UnboundedReceiver;
+use rand::seq::SliceRandom;
+use reversi_ai::{best_move, legal_moves, DiscCount, Weights};
+
+pub const COMPUTER_NAME: &str = "computer";
+
+/// Level of the computer opponent handed out by matchmaking.
+pub const MATCHMAKING_LEVEL: u8 = 3;
+
+/// A move the computer has to find, taken out of `ServerData` so that the
+/// search runs without holding the lock.
+pub struct ComputerMove {
+    game_id: GameID,
+    /// Length of the game record when the request was made. A different
+    /// length afterwards means the game moved on and the answer is stale.
+    ply: usize,
+    field: Field,
+    color: BorW,
+    level: u8,
+    /// When the move was asked for, to measure how long it took.
+    requested: Instant,
+}
+
+/// Move selection for every level.
+#[derive(Default)]
+pub struct Engine {
+    /// Pattern weights for the strongest level; disc count is used without.
+    pub weights: Option<Weights>,
+}
+
+impl Engine {
+    /// Level 1 plays at random, 2 takes the most discs, 3 searches three
+    /// plies and 4 searches five plies.
+    pub fn choose(&self, field: &Field, color: BorW, level: u8) -> Option<Position> {
+        match level {
+            1 => legal_moves(field, color)
+                .choose(&mut rand::thread_rng())
+                .copied(),
+            2 => best_move(field, color, 1, &DiscCount),
+            3 => best_move(field, color, 3, &DiscCount),
+            _ => match &self.weights {
+                Some(x) => best_move(field, color, 5, x),
+                None => best_move(field, color, 5, &DiscCount),
+            },
+        }
+    }
+}
+
+/// Asks the computer worker for a move if the computer is on turn.
+pub fn play_computer(server_data: &ServerData, game_id: GameID) {
+    let game = match server_data.games.get(&game_id) {
+        Some(x) => x,
+        None => return,
+    };
+    if game.color_of(PlayerId::Computer) != Some(game.data.turn)
+        || !game.data.field.puttable(game.data.turn)
+    {
+        return;
+    }
+    // The worker only goes away when the server shuts down.
+    let _ = server_data.computer_tx.unbounded_send(ComputerMove {
+        game_id,
+        ply: game.record.moves.len(),
+        field: game.data.field.clone(),
+        color: game.data.turn,
+        level: game.level,
+        requested: Instant::now(),
+    });
+}
+
+/// Plays a move found by the worker, unless the game changed meanwhile.
+fn apply_computer_move(server_data: &mut ServerData, request: &ComputerMove, p: Position) {
+    let now = Instant::now();
+    check_flags(server_data, now);
+    let game = match server_data.games.get_mut(&request.game_id) {
+        Some(x) if !x.is_over() => x,
+        _ => return,
+    };
+    if game.record.moves.len() != request.ply
+        || game.color_of(PlayerId::Computer) != Some(request.color)
+    {
+        return;
+    }
+    let update = match game.play(p, now) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    server_data.save_game(request.game_id);
+    broadcast_to_game(server_data, request.game_id, &update);
+    server_data
+        .metrics
+        .observe_move(Mover::Computer, request.requested.elapsed());
+    finish_game(server_data, request.game_id);
+    // The human may have had to pass.
+    play_computer(server_data, request.game_id);
+}
+
+pub async fn computer_worker(
+    server_data: Arc<Mutex<ServerData>>,
+    mut requests: UnboundedReceiver<ComputerMove>,
+    engine: Arc<Engine>,
+) {
+    while let Some(request) = requests.next().await {
+        let server_data = server_data.clone();
+        let engine = engine.clone();
+        tokio::spawn(async move {
+            let search_engine = engine.clone();
+            let (field, color, level) = (request.field.clone(), request.color, request.level);
+            let choice =
+                tokio::task::spawn_blocking(move || search_engine.choose(&field, color, level))
+                    .await;
+            if let Ok(Some(p)) = choice {
+                apply_computer_move(&mut lock(&server_data), &request, p);
+            }
+        });
+    }
+}
+
+/// Answers every pending request on the current thread.
+#[cfg(test)]
+pub fn run_pending(
+    server_data: &mut ServerData,
+    requests: &mut UnboundedReceiver<ComputerMove>,
+    engine: &Engine,
+) {
+    while let Ok(Some(request)) = requests.try_next() {
+        if let Some(p) = engine.choose(&request.field, request.color, request.level) {
+            apply_computer_move(server_data, &request, p);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
+
+/// Command line flags. Every flag can also be set through its environment
+/// variable; anything left unset falls back to the config file and then to
+/// the built-in default.
+#[derive(Parser, Default)]
+#[command(about = "Reversi game server")]
+pub struct Args {
+    /// TOML file with the same keys as the long flags, in snake_case.
+    #[arg(long, env = "REVERSI_CONFIG")]
+    config: Option<PathBuf>,
+    #[arg(long, env = "REVERSI_ADDRESS")]
+    address: Option<String>,
+    #[arg(long, env = "REVERSI_PORT")]
+    port: Option<u16>,
+    #[arg(long, env = "REVERSI_MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
+    #[arg(long, env = "REVERSI_MAX_GAMES")]
+    max_games: Option<usize>,
+    /// One of off, error, warn, info, debug or trace.
+    #[arg(long, env = "REVERSI_LOG_LEVEL")]
+    log_level: Option<LevelFilter>,
+    /// Directory where games are saved; without it nothing survives a restart.
+    #[arg(long, env = "REVERSI_STORAGE_DIR")]
+    storage_dir: Option<PathBuf>,
+    /// Seconds a disconnected player keeps their seat.
+    #[arg(long, env = "REVERSI_RECONNECT_GRACE")]
+    reconnect_grace: Option<u64>,
+    /// Seconds in the matchmaking queue before a computer opponent is found.
+    #[arg(long, env = "REVERSI_MATCH_TIMEOUT")]
+    match_timeout: Option<u64>,
+    /// Seconds an empty game is kept before it is archived or dropped.
+    #[arg(long, env = "REVERSI_GAME_IDLE_TIMEOUT")]
+    game_idle_timeout: Option<u64>,
+    /// Pattern weights written by reversi_train, used by the strongest
+    /// computer level.
+    #[arg(long, env = "REVERSI_WEIGHTS")]
+    weights: Option<PathBuf>,
+    /// Directory served over HTTP, such as a built web client.
+    #[arg(long, env = "REVERSI_STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+    /// PEM certificate chain; with `tls_key` the server speaks only TLS.
+    #[arg(long, env = "REVERSI_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`.
+    #[arg(long, env = "REVERSI_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    address: Option<String>,
+    port: Option<u16>,
+    max_connections: Option<usize>,
+    max_games: Option<usize>,
+    log_level: Option<String>,
+    storage_dir: Option<PathBuf>,
+    reconnect_grace: Option<u64>,
+    match_timeout: Option<u64>,
+    game_idle_timeout: Option<u64>,
+    weights: Option<PathBuf>,
+    static_dir: Option<PathBuf>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+}
+
+pub struct Config {
+    pub address: String,
+    pub port: u16,
+    pub max_connections: usize,
+    pub max_games: usize,
+    pub log_level: LevelFilter,
+    pub storage_dir: Option<PathBuf>,
+    pub reconnect_grace: Duration,
+    pub match_timeout: Duration,
+    pub game_idle_timeout: Duration,
+    pub weights: Option<PathBuf>,
+    pub static_dir: Option<PathBuf>,
+    /// Certificate and key files, set together or not at all.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1".to_string(),
+            port: 9001,
+            max_connections: 1024,
+            max_games: 1000,
+            log_level: LevelFilter::INFO,
+            storage_dir: None,
+            reconnect_grace: Duration::from_secs(60),
+            match_timeout: Duration::from_secs(30),
+            game_idle_timeout: Duration::from_secs(600),
+            weights: None,
+            static_dir: None,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, String> {
+        Self::from_args(Args::parse())
+    }
+
+    fn from_args(args: Args) -> Result<Self, String> {
+        let file = match &args.config {
+            Some(path) => read_file(path)?,
+            None => FileConfig::default(),
+        };
+        let default = Config::default();
+        let log_level = match (args.log_level, file.log_level) {
+            (Some(x), _) => x,
+            (None, Some(x)) => x.parse().map_err(|_| format!("invalid log_level {}", x))?,
+            (None, None) => default.log_level,
+        };
+        let tls_cert = args.tls_cert.or(file.tls_cert);
+        let tls_key = args.tls_key.or(file.tls_key);
+        if tls_cert.is_some() != tls_key.is_some() {
+            return Err("tls_cert and tls_key have to be set together".to_string());
+        }
+        Ok(Config {
+            address: args.address.or(file.address).unwrap_or(default.address),
+            port: args.port.or(file.port).unwrap_or(default.port),
+            max_connections: args
+                .max_connections
+                .or(file.max_connections)
+                .unwrap_or(default.max_connections),
+            max_games: args
+                .max_games
+                .or(file.max_games)
+                .unwrap_or(default.max_games),
+            log_level,
+            storage_dir: args.storage_dir.or(file.storage_dir),
+            reconnect_grace: args
+                .reconnect_grace
+                .or(file.reconnect_grace)
+                .map_or(default.reconnect_grace, Duration::from_secs),
+            match_timeout: args
+                .match_timeout
+                .or(file.match_timeout)
+                .map_or(default.match_timeout, Duration::from_secs),
+            game_idle_timeout: args
+                .game_idle_timeout
+                .or(file.game_idle_timeout)
+                .map_or(default.game_idle_timeout, Duration::from_secs),
+            weights: args.weights.or(file.weights),
+            static_dir: args.static_dir.or(file.static_dir),
+            tls_cert,
+            tls_key,
+        })
+    }
+
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.address, self.port)
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn precedence_test() {
+        let path = std::env::temp_dir().join(format!("reversi_config_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "port = 8000\nmax_games = 5\nlog_level = \"debug\"\nstorage_dir = \"games\"\n",
+        )
+        .unwrap();
+        let args = Args {
+            config: Some(path.clone()),
+            port: Some(9000),
+            ..Args::default()
+        };
+        let config = Config::from_args(args).unwrap();
+        assert!(config.port == 9000);
+        assert!(config.max_games == 5);
+        assert!(config.log_level == LevelFilter::DEBUG);
+        assert!(config.bind_address() == "127.0.0.1:9000");
+        assert!(config.storage_dir == Some(PathBuf::from("games")));
+        std::fs::write(&path, "prot = 8000\n").unwrap();
+        let args = Args {
+            config: Some(path.clone()),
+            ..Args::default()
+        };
+        assert!(Config::from_args(args).is_err());
+        let args = Args {
+            tls_cert: Some(PathBuf::from("cert.pem")),
+            ..Args::default()
+        };
+        assert!(Config::from_args(args).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
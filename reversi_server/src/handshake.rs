@@ -0,0 +1,77 @@
+use super::*;
+
+/// Oldest `PROTOCOL_VERSION` this server still speaks.
+const OLDEST_PROTOCOL_VERSION: u32 = 1;
+
+const FEATURES: [Feature; 8] = [
+    Feature::Sessions,
+    Feature::Chat,
+    Feature::Matchmaking,
+    Feature::Ratings,
+    Feature::Computer,
+    Feature::Clocks,
+    Feature::Negotiation,
+    Feature::Deltas,
+];
+
+/// Encodings this server can speak, in the order it prefers them.
+const ENCODINGS: [Encoding; 3] = [Encoding::MessagePack, Encoding::Cbor, Encoding::Json];
+
+pub fn handle_hello(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    protocol_version: u32,
+    client_name: String,
+    encodings: Vec<Encoding>,
+) -> Result<(), ClientError> {
+    if !(OLDEST_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+        reject(
+            addr,
+            server_data,
+            format!(
+                "{} speaks protocol version {}, but this server supports {} to {}",
+                client_name, protocol_version, OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        );
+        return Ok(());
+    }
+    // The client's preference wins; JSON is what everyone understands.
+    let encoding = encodings
+        .into_iter()
+        .find(|x| ENCODINGS.contains(x))
+        .unwrap_or(Encoding::Json);
+    info!(client_name, protocol_version, ?encoding, "greeted");
+    let connection = server_data.connections.get_mut(&addr).unwrap();
+    let first = !connection.greeted;
+    connection.greeted = true;
+    // The reply itself is JSON so that the client can always read it.
+    connection.sender.encoding = Encoding::Json;
+    send_message(
+        &connection.sender,
+        &ServerMessage::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: FEATURES.to_vec(),
+            encoding,
+        },
+    );
+    connection.sender.encoding = encoding;
+    if first {
+        send_scrollback(addr, server_data);
+    }
+    Ok(())
+}
+
+/// Tells the client why it cannot be served and closes the connection.
+pub fn reject(addr: SocketAddr, server_data: &ServerData, reason: String) {
+    warn!(%reason, "rejecting");
+    let sender = &server_data.connections.get(&addr).unwrap().sender;
+    send_message(
+        sender,
+        &ServerMessage::Rejected {
+            protocol_version: PROTOCOL_VERSION,
+            reason,
+        },
+    );
+    // The connection is going away anyway if this fails.
+    let _ = sender.frames.unbounded_send(Message::Close(None));
+}
@@ -0,0 +1,392 @@
+use super::*;
+use serde::Serialize;
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Longest request head accepted, in bytes.
+const MAX_HEAD: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+/// A stream with the bytes already read from it put back in front.
+pub struct Prefixed<S> {
+    prefix: Vec<u8>,
+    read: usize,
+    inner: S,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Prefixed<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.read < this.prefix.len() {
+            let n = (this.prefix.len() - this.read).min(buf.remaining());
+            buf.put_slice(&this.prefix[this.read..this.read + n]);
+            this.read += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Prefixed<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// What a new connection asked for.
+pub enum Request<S> {
+    /// The handshake is still unread in the stream.
+    WebSocket(Prefixed<S>),
+    Http {
+        stream: S,
+        method: String,
+        path: String,
+    },
+}
+
+/// Reads the request head to tell WebSocket upgrades from plain HTTP.
+pub async fn sniff<S: AsyncRead + Unpin>(mut stream: S) -> io::Result<Request<S>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut head = Vec::new();
+    let mut chunk = [0; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&chunk[..n]);
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&head) {
+            Ok(httparse::Status::Complete(_)) => {
+                let upgrade = request.headers.iter().any(|x| {
+                    x.name.eq_ignore_ascii_case("upgrade")
+                        && x.value.eq_ignore_ascii_case(b"websocket")
+                });
+                if upgrade {
+                    return Ok(Request::WebSocket(Prefixed {
+                        prefix: head,
+                        read: 0,
+                        inner: stream,
+                    }));
+                }
+                return Ok(Request::Http {
+                    method: request.method.unwrap_or_default().to_string(),
+                    path: request.path.unwrap_or_default().to_string(),
+                    stream,
+                });
+            }
+            Ok(httparse::Status::Partial) if head.len() < MAX_HEAD => {}
+            Ok(httparse::Status::Partial) => {
+                return Err(invalid("request head too long".to_string()))
+            }
+            Err(e) => return Err(invalid(e.to_string())),
+        }
+    }
+}
+
+pub struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Self {
+        Response {
+            status: 200,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap(),
+        }
+    }
+    fn error(status: u16, message: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", message).into_bytes(),
+        }
+    }
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// A game as `GET /games/{id}` shows it: what is saved of it and the
+/// position after the last move.
+#[derive(Serialize)]
+struct GameDetail {
+    #[serde(flatten)]
+    game: SavedGame,
+    data: ReversiData,
+}
+
+/// A game in play or in the archive.
+fn find_game(server_data: &ServerData, game_id: GameID) -> Option<SavedGame> {
+    server_data.saved_game(game_id).or_else(|| {
+        server_data
+            .archive
+            .iter()
+            .rev()
+            .find(|x| x.id == game_id)
+            .cloned()
+    })
+}
+
+fn transcript(saved: SavedGame) -> Result<String, Error> {
+    let game = Game::from_record(saved.record.clone())?;
+    let mut ggf = GgfGame::new(saved.record);
+    ggf.black = saved.players.black;
+    ggf.white = saved.players.white;
+    // Only a full board says by how much; resignations and time losses are
+    // not part of the record.
+    if !matches!(game.data.field.get_gameresult(), GameResult::Playing) {
+        let field = &game.data.field;
+        let margin = field.count(BorW::Black) as i32 - field.count(BorW::White) as i32;
+        ggf.result = Some(format!("{:+}.000", margin).replace("+0.", "0."));
+    }
+    Ok(ggf.to_ggf())
+}
+
+/// Whether `path` belongs to the API rather than to the static files.
+fn is_api(path: &str) -> bool {
+    let first = path.trim_start_matches('/').split(['/', '?']).next();
+    matches!(first, Some("games" | "metrics"))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        // Browsers only compile streamed WebAssembly with this type.
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css; charset=utf-8",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Reads the file under `dir` that `path` names, `index.html` for
+/// directories.
+async fn serve_file(dir: &Path, path: &str) -> Response {
+    let path = path.split('?').next().unwrap_or_default();
+    let mut relative = path.trim_start_matches('/').to_string();
+    if relative.is_empty() || relative.ends_with('/') {
+        relative += "index.html";
+    }
+    // Nothing outside `dir` is served.
+    if relative
+        .split('/')
+        .any(|x| x.is_empty() || x == ".." || x.contains('\\'))
+    {
+        return Response::error(404, "no such file");
+    }
+    let file = dir.join(relative);
+    match tokio::fs::read(&file).await {
+        Ok(body) => Response {
+            status: 200,
+            content_type: content_type(&file),
+            body,
+        },
+        Err(e) => {
+            if !matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::IsADirectory
+            ) {
+                warn!(file = %file.display(), error = %e, "cannot serve");
+            }
+            Response::error(404, "no such file")
+        }
+    }
+}
+
+/// Answers a read-only request about the games on this server.
+pub fn route(server_data: &ServerData, method: &str, path: &str) -> Response {
+    if method != "GET" {
+        return Response::error(405, "only GET is supported");
+    }
+    let path = path.split('?').next().unwrap_or_default();
+    let segments = path.trim_matches('/').split('/').collect::<Vec<&str>>();
+    let (id, rest) = match segments.as_slice() {
+        ["games"] => return Response::json(&get_game_summary_list(server_data, None)),
+        ["metrics"] => {
+            return Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: metrics::render(server_data).into_bytes(),
+            }
+        }
+        ["games", id, rest @ ..] => (id, rest),
+        _ => return Response::error(404, "no such resource"),
+    };
+    let saved = match id
+        .parse()
+        .ok()
+        .and_then(|x| find_game(server_data, GameID(x)))
+    {
+        Some(x) => x,
+        None => return Response::error(404, "no such game"),
+    };
+    match rest {
+        [] => match Game::from_record(saved.record.clone()) {
+            Ok(game) => Response::json(&GameDetail {
+                game: saved,
+                data: game.data,
+            }),
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        ["transcript"] => match transcript(saved) {
+            Ok(body) => Response {
+                status: 200,
+                content_type: "text/plain; charset=utf-8",
+                body: body.into_bytes(),
+            },
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        _ => Response::error(404, "no such resource"),
+    }
+}
+
+/// Answers one HTTP request and closes the connection.
+pub async fn respond<S: AsyncWrite + Unpin>(
+    mut stream: S,
+    server_data: &Mutex<ServerData>,
+    method: &str,
+    path: &str,
+) -> io::Result<()> {
+    let static_dir = lock(server_data).static_dir.clone();
+    let response = match static_dir {
+        Some(dir) if method == "GET" && !is_api(path) => serve_file(&dir, path).await,
+        _ => route(&lock(server_data), method, path),
+    };
+    info!(method, path, status = response.status, "http request");
+    let allow = if response.status == 405 {
+        "Allow: GET\r\n"
+    } else {
+        ""
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len(),
+        allow
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn sniff_test() {
+        let upgrade =
+            "GET / HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n";
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(upgrade.as_bytes()).await.unwrap();
+        client.write_all(b"frame").await.unwrap();
+        match sniff(server).await.unwrap() {
+            Request::WebSocket(mut x) => {
+                let mut replayed = vec![0; upgrade.len() + 5];
+                x.read_exact(&mut replayed).await.unwrap();
+                assert!(replayed == format!("{}frame", upgrade).as_bytes());
+            }
+            Request::Http { .. } => panic!("an upgrade has to go to the WebSocket"),
+        }
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"GET /games HTT").await.unwrap();
+        client.write_all(b"P/1.1\r\n\r\n").await.unwrap();
+        match sniff(server).await.unwrap() {
+            Request::Http { method, path, .. } => assert!(method == "GET" && path == "/games"),
+            Request::WebSocket(_) => panic!("a plain GET has to be answered over HTTP"),
+        }
+    }
+    #[test]
+    fn route_test() {
+        let mut server_data = ServerData::new(2, Box::new(MemoryStore));
+        let live = add_new_game(&mut server_data).unwrap();
+        let game = server_data.games.get_mut(&live).unwrap();
+        game.put(Position::from_notation("f5").unwrap()).unwrap();
+        let mut finished = Game::new();
+        for p in ["e6", "f4", "e3", "f6", "g5", "d6", "e7", "f5", "c5"] {
+            finished.put(Position::from_notation(p).unwrap()).unwrap();
+        }
+        server_data.archive.push_back(SavedGame {
+            id: GameID(7),
+            record: finished.record,
+            time_control: TimeControl::Unlimited,
+            state: GameState::Finished,
+            players: Players {
+                black: Some("alice".to_string()),
+                white: Some("bob".to_string()),
+                spectators: Vec::new(),
+            },
+            black: None,
+            white: None,
+        });
+        let list = route(&server_data, "GET", "/games");
+        assert!(list.status == 200);
+        let list = serde_json::from_slice::<Vec<GameSummary>>(&list.body).unwrap();
+        assert!(list.len() == 1 && list[0].id == live && !list[0].your);
+        let detail = route(&server_data, "GET", "/games/1?pretty");
+        let detail = serde_json::from_slice::<serde_json::Value>(&detail.body).unwrap();
+        assert!(detail["record"]["moves"].as_array().unwrap().len() == 1);
+        assert!(detail["data"]["turn"] == "white");
+        let ggf = route(&server_data, "GET", "/games/7/transcript");
+        let ggf = String::from_utf8(ggf.body).unwrap();
+        assert!(ggf.contains("PB[alice]"));
+        let parsed = GgfGame::parse(&ggf).unwrap();
+        assert!(parsed.record.moves.len() == 9 && parsed.result.is_some());
+        assert!(route(&server_data, "GET", "/games/8").status == 404);
+        assert!(route(&server_data, "GET", "/games/x/transcript").status == 404);
+        assert!(route(&server_data, "GET", "/").status == 404);
+        assert!(route(&server_data, "POST", "/games").status == 405);
+    }
+    #[tokio::test]
+    async fn static_test() {
+        let dir = std::env::temp_dir().join(format!("reversi_static_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("pkg")).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join("pkg/client_bg.wasm"), b"\0asm").unwrap();
+        let index = serve_file(&dir, "/?x=1").await;
+        assert!(index.status == 200 && index.body == b"<html></html>");
+        assert!(index.content_type.starts_with("text/html"));
+        let wasm = serve_file(&dir, "/pkg/client_bg.wasm").await;
+        assert!(wasm.status == 200 && wasm.content_type == "application/wasm");
+        assert!(serve_file(&dir, "/pkg").await.status == 404);
+        assert!(serve_file(&dir, "/missing.js").await.status == 404);
+        assert!(serve_file(&dir, "/pkg/../../etc/passwd").await.status == 404);
+        assert!(is_api("/games/1") && is_api("/games?x") && !is_api("/gamesx"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
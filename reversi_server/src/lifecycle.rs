@@ -0,0 +1,66 @@
+use super::*;
+
+/// Archived games kept in memory; older ones are only in the store.
+pub const ARCHIVE_SIZE: usize = 100;
+
+/// Whether nobody is in the game, counting players who are reconnecting.
+fn is_empty(server_data: &ServerData, game_id: GameID) -> bool {
+    !server_data
+        .connections
+        .values()
+        .any(|x| x.game_id == Some(game_id))
+        && !server_data.away.values().any(|x| x.0 == Some(game_id))
+}
+
+/// Removes games that have been empty and idle for `game_idle_timeout`.
+/// Games that never started are dropped; the rest are archived, as abandoned
+/// if they were still being played.
+pub fn sweep_games(server_data: &mut ServerData, now: Instant) {
+    let timeout = server_data.game_idle_timeout;
+    let idle = server_data
+        .games
+        .iter()
+        .filter(|(id, game)| {
+            now.saturating_duration_since(game.touched) >= timeout && is_empty(server_data, **id)
+        })
+        .map(|x| *x.0)
+        .collect::<Vec<GameID>>();
+    if idle.is_empty() {
+        return;
+    }
+    for game_id in idle {
+        let game = server_data.games.get_mut(&game_id).unwrap();
+        match game.state {
+            GameState::Waiting => {
+                server_data.games.remove(&game_id);
+                if let Err(e) = server_data.store.remove_game(game_id) {
+                    warn!(game_id = game_id.0, error = %e, "cannot remove game");
+                }
+                info!(game_id = game_id.0, "expired");
+                continue;
+            }
+            GameState::Playing => {
+                game.state = GameState::Abandoned;
+                if let Some(clock) = &mut game.clock {
+                    clock.punch(None, now);
+                }
+                info!(game_id = game_id.0, "abandoned");
+            }
+            GameState::Finished | GameState::Abandoned => {}
+        }
+        let saved = server_data.saved_game(game_id).unwrap();
+        server_data.games.remove(&game_id);
+        archive_game(server_data, saved);
+    }
+    broadcast_game_list(server_data);
+}
+
+fn archive_game(server_data: &mut ServerData, saved: SavedGame) {
+    if let Err(e) = server_data.store.archive_game(&saved) {
+        warn!(game_id = saved.id.0, error = %e, "cannot archive game");
+    }
+    server_data.archive.push_back(saved);
+    if server_data.archive.len() > ARCHIVE_SIZE {
+        server_data.archive.pop_front();
+    }
+}
@@ -1,37 +1,252 @@
+mod clock;
+mod computer;
+mod config;
+mod handshake;
+mod http;
+mod lifecycle;
+mod matchmaking;
+mod metrics;
+mod negotiation;
+mod ratings;
+mod storage;
+mod tls;
+
+use clock::{check_flags, Clock};
+use computer::{
+    computer_worker, play_computer, ComputerMove, Engine, COMPUTER_NAME, MATCHMAKING_LEVEL,
+};
+use config::Config;
+use handshake::{handle_hello, reject};
+use http::{respond, sniff, Request};
+use lifecycle::{sweep_games, ARCHIVE_SIZE};
+use matchmaking::{handle_cancelmatch, handle_findmatch, leave_queue, match_players, QueuedPlayer};
+use metrics::{Metrics, Mover};
+use negotiation::{handle_accept, handle_decline, handle_offer, handle_resign};
+use ratings::{finish_game, handle_leaderboard, rating, save_ratings, DEFAULT_RATING};
 use reversi_core::*;
 use reversi_message::*;
 use std::{
-    collections::HashMap,
-    io::Error as IoError,
+    collections::{HashMap, VecDeque},
     net::SocketAddr,
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use storage::{FileStore, GameStore, MemoryStore, SavedGame, SavedSeat};
 
-use futures_channel::mpsc::{unbounded, UnboundedSender};
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
 
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, info_span, warn, Instrument};
 use tungstenite::protocol::Message;
 
-type Tx = UnboundedSender<Message>;
+/// Frames on their way to one client, and how messages are encoded in them.
+struct Tx {
+    frames: UnboundedSender<Message>,
+    /// Agreed on in `Hello`; JSON until then.
+    encoding: Encoding,
+}
+
+/// Chat lines kept per room and replayed to whoever joins it.
+const CHAT_SCROLLBACK: usize = 20;
+
+/// Time a new connection gets for the TLS handshake and again for sending
+/// its request head.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_millis() as u64)
+}
 
 fn send_message(tx: &Tx, message: &ServerMessage) {
-    tx.unbounded_send(Message::Text(serde_json::to_string(&*message).unwrap()))
-        .unwrap();
+    // Every server message can be represented in every encoding.
+    let bytes = tx.encoding.encode(message).unwrap();
+    let frame = if tx.encoding.is_binary() {
+        Message::Binary(bytes)
+    } else {
+        Message::Text(String::from_utf8(bytes).unwrap())
+    };
+    // The receiving task may already be gone; its cleanup drops the connection.
+    let _ = tx.frames.unbounded_send(frame);
+}
+
+/// An error reported back to the client that caused it.
+struct ClientError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl ClientError {
+    fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ClientError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for ClientError {
+    fn from(e: Error) -> Self {
+        ClientError::new(ErrorCode::IllegalMove, e.to_string())
+    }
+}
+
+fn lock(server_data: &Mutex<ServerData>) -> MutexGuard<'_, ServerData> {
+    // Handlers validate before mutating, so the data is still usable after a
+    // panic in another connection.
+    server_data.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
 struct ServerData {
     connections: HashMap<SocketAddr, ConnectionData>,
-    games: HashMap<GameID, ReversiData>,
+    games: HashMap<GameID, Game>,
+    max_games: usize,
+    /// ID of the next game created; IDs are never reused.
+    next_game_id: u32,
+    /// Most recently archived games, oldest first.
+    archive: VecDeque<SavedGame>,
+    /// How long an empty game is kept.
+    game_idle_timeout: Duration,
+    store: Box<dyn GameStore>,
+    /// Recent chat per room; `None` is the lobby.
+    chats: HashMap<Option<GameID>, VecDeque<ServerMessage>>,
+    /// Nickname of every session handed out by `Login`, and of every rated
+    /// session ever.
+    sessions: HashMap<SessionToken, String>,
+    /// Sessions whose connection dropped, with the game they were in. They
+    /// keep their seat until `reconnect_grace` has passed.
+    away: HashMap<SessionToken, (Option<GameID>, Instant)>,
+    reconnect_grace: Duration,
+    /// Players waiting for `FindMatch`, oldest first.
+    queue: Vec<QueuedPlayer>,
+    /// Ratings by session; players missing here have the default rating.
+    ratings: HashMap<SessionToken, f64>,
+    match_timeout: Duration,
+    /// Files served to plain HTTP requests outside the API.
+    static_dir: Option<PathBuf>,
+    metrics: Metrics,
+    /// Requests for the computer worker, whose receiving end is taken by
+    /// whoever runs the worker.
+    computer_tx: UnboundedSender<ComputerMove>,
+    computer_rx: Option<UnboundedReceiver<ComputerMove>>,
 }
 
 impl ServerData {
-    fn new() -> Self {
+    fn new(max_games: usize, store: Box<dyn GameStore>) -> Self {
+        let (computer_tx, computer_rx) = unbounded();
         return ServerData {
             connections: HashMap::new(),
             games: HashMap::new(),
+            max_games,
+            next_game_id: 1,
+            archive: VecDeque::new(),
+            game_idle_timeout: Duration::from_secs(600),
+            store,
+            chats: HashMap::new(),
+            sessions: HashMap::new(),
+            away: HashMap::new(),
+            reconnect_grace: Duration::from_secs(60),
+            queue: Vec::new(),
+            ratings: HashMap::new(),
+            match_timeout: Duration::from_secs(30),
+            static_dir: None,
+            metrics: Metrics::default(),
+            computer_tx,
+            computer_rx: Some(computer_rx),
         };
     }
+    /// Loads every game and rating the store kept from a previous run.
+    /// Players seated in a restored game count as away, so they get
+    /// `reconnect_grace` to resume their session.
+    fn restore(&mut self) -> std::io::Result<()> {
+        for (token, rated) in self.store.load_ratings()? {
+            self.ratings.insert(token, rated.rating);
+            self.sessions.insert(token, rated.nickname);
+        }
+        let archive = self.store.load_archive()?;
+        self.archive = archive.into_iter().rev().take(ARCHIVE_SIZE).rev().collect();
+        let games = self.store.load_games()?;
+        self.next_game_id = games
+            .iter()
+            .chain(self.archive.iter())
+            .map(|x| x.id.0 + 1)
+            .max()
+            .unwrap_or(1);
+        for saved in games {
+            let mut game = Game::from_record(saved.record).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("game {}: {}", saved.id.0, e),
+                )
+            })?;
+            game.set_time_control(saved.time_control);
+            game.black = saved
+                .black
+                .map(|x| self.restore_seat(saved.id, x, &mut game));
+            game.white = saved
+                .white
+                .map(|x| self.restore_seat(saved.id, x, &mut game));
+            if game.state != GameState::Finished {
+                game.state = saved.state;
+            }
+            if game.state == GameState::Playing {
+                game.start(Instant::now());
+            }
+            self.games.insert(saved.id, game);
+            play_computer(self, saved.id);
+        }
+        Ok(())
+    }
+    fn restore_seat(&mut self, game_id: GameID, seat: SavedSeat, game: &mut Game) -> PlayerId {
+        match seat {
+            SavedSeat::Session { token, nickname } => {
+                self.sessions.entry(token).or_insert(nickname);
+                self.away
+                    .entry(token)
+                    .or_insert((Some(game_id), Instant::now()));
+                PlayerId::Session(token)
+            }
+            SavedSeat::Computer { level } => {
+                game.level = level;
+                PlayerId::Computer
+            }
+        }
+    }
+    /// Persists a game. A failing store only costs durability, so the game
+    /// goes on and the error is logged.
+    fn save_game(&self, game_id: GameID) {
+        if let Some(saved) = self.saved_game(game_id) {
+            if let Err(e) = self.store.save_game(&saved) {
+                warn!(game_id = game_id.0, error = %e, "cannot save game");
+            }
+        }
+    }
+    fn saved_game(&self, game_id: GameID) -> Option<SavedGame> {
+        let game = self.games.get(&game_id)?;
+        let seat = |player: Option<PlayerId>| match player? {
+            PlayerId::Session(token) => Some(SavedSeat::Session {
+                token,
+                nickname: self.sessions.get(&token)?.clone(),
+            }),
+            PlayerId::Guest(_) => None,
+            PlayerId::Computer => Some(SavedSeat::Computer { level: game.level }),
+        };
+        Some(SavedGame {
+            id: game_id,
+            record: game.record.clone(),
+            time_control: game.time_control,
+            state: game.state,
+            players: get_players(self, game_id),
+            black: seat(game.black),
+            white: seat(game.white),
+        })
+    }
     fn add_connectin(&mut self, addr: SocketAddr, connection: ConnectionData) {
         self.connections.insert(addr, connection);
     }
@@ -40,153 +255,1718 @@ impl ServerData {
 struct ConnectionData {
     game_id: Option<GameID>,
     sender: Tx,
+    nickname: String,
+    token: Option<SessionToken>,
+    /// Set once the client has sent an acceptable `Hello`.
+    greeted: bool,
 }
 
-fn get_game_summary_list(server_data: &ServerData, addr: &SocketAddr) -> Vec<GameSummary> {
+impl ConnectionData {
+    fn new(addr: SocketAddr, frames: UnboundedSender<Message>) -> Self {
+        ConnectionData {
+            game_id: None,
+            sender: Tx {
+                frames,
+                encoding: Encoding::Json,
+            },
+            nickname: format!("guest{}", addr.port()),
+            token: None,
+            greeted: false,
+        }
+    }
+    fn player_id(&self, addr: SocketAddr) -> PlayerId {
+        match self.token {
+            Some(x) => PlayerId::Session(x),
+            None => PlayerId::Guest(addr),
+        }
+    }
+}
+
+/// Who holds a seat. Logged in players keep it across reconnects, guests
+/// lose it with their connection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PlayerId {
+    Session(SessionToken),
+    Guest(SocketAddr),
+    Computer,
+}
+
+struct Game {
+    data: ReversiData,
+    record: GameRecord,
+    black: Option<PlayerId>,
+    white: Option<PlayerId>,
+    state: GameState,
+    /// Last time someone joined, left or moved.
+    touched: Instant,
+    /// Strength of the computer, if it holds a seat.
+    level: u8,
+    time_control: TimeControl,
+    /// `None` for games without a time limit.
+    clock: Option<Clock>,
+    /// The open offer and who made it; a move withdraws it.
+    offer: Option<(Proposal, BorW)>,
+    /// Number of the last update sent to the members.
+    seq: u64,
+}
+
+impl Game {
+    fn new() -> Self {
+        Game {
+            data: ReversiData::new(),
+            record: GameRecord::new(),
+            black: None,
+            white: None,
+            state: GameState::Waiting,
+            touched: Instant::now(),
+            level: MATCHMAKING_LEVEL,
+            time_control: TimeControl::Unlimited,
+            clock: None,
+            offer: None,
+            seq: 0,
+        }
+    }
+    fn set_time_control(&mut self, time_control: TimeControl) {
+        self.time_control = time_control;
+        self.clock = Clock::new(time_control);
+    }
+    /// Rebuilds the board by replaying the puts of `record`.
+    fn from_record(record: GameRecord) -> Result<Self, Error> {
+        let mut game = Game::new();
+        // Forced passes are recorded again by `put`.
+        for mv in record.moves.iter() {
+            if let Move::Put(p) = *mv {
+                game.put(p)?;
+            }
+        }
+        if !matches!(game.data.field.get_gameresult(), GameResult::Playing) {
+            game.state = GameState::Finished;
+        }
+        Ok(game)
+    }
+    fn is_over(&self) -> bool {
+        matches!(self.state, GameState::Finished | GameState::Abandoned)
+    }
+    fn put(&mut self, position: Position) -> Result<(), Error> {
+        self.data.cursor = position;
+        try_put(&mut self.data)?;
+        self.record.play(position)
+    }
+    /// Puts for the side on turn and hands the move to the other clock.
+    /// Returns the update to send to the members.
+    fn play(&mut self, position: Position, now: Instant) -> Result<ServerMessage, Error> {
+        let (before, color) = (self.data.field.clone(), self.data.turn);
+        self.put(position)?;
+        self.seq += 1;
+        self.touched = now;
+        self.offer = None;
+        let next = match self.data.field.get_gameresult() {
+            GameResult::Playing => Some(self.data.turn),
+            _ => None,
+        };
+        if let Some(clock) = self.clock.as_mut().filter(|x| x.is_running()) {
+            clock.punch(next, now);
+        }
+        let flipped = (0..8)
+            .flat_map(|y| (0..8).map(move |x| Position::new(x, y).unwrap()))
+            .filter(|&p| p != position && before.get(p) != self.data.field.get(p))
+            .collect();
+        Ok(ServerMessage::MoveApplied {
+            seq: self.seq,
+            color,
+            position,
+            flipped,
+            turn: self.data.turn,
+            clock: self.clock.as_ref().map(|x| x.state(now)),
+        })
+    }
+    /// Starts play, and the clock of the side on turn, once both seats are
+    /// taken.
+    fn start(&mut self, now: Instant) {
+        if self.black.is_none() || self.white.is_none() || self.is_over() {
+            return;
+        }
+        self.state = GameState::Playing;
+        if let Some(clock) = self.clock.as_mut().filter(|x| !x.is_running()) {
+            clock.punch(Some(self.data.turn), now);
+        }
+    }
+    fn view(&self, now: Instant) -> ServerMessage {
+        ServerMessage::View {
+            seq: self.seq,
+            data: self.data.to_owned(),
+            clock: self.clock.as_ref().map(|x| x.state(now)),
+        }
+    }
+    fn join(&mut self, player: PlayerId) -> Role {
+        self.touched = Instant::now();
+        if let Some(color) = self.color_of(player) {
+            return Role::Player(color);
+        }
+        if self.black.is_none() {
+            self.black = Some(player);
+            return Role::Player(BorW::Black);
+        }
+        if self.white.is_none() {
+            self.white = Some(player);
+            return Role::Player(BorW::White);
+        }
+        Role::Spectator
+    }
+    fn leave(&mut self, player: PlayerId) {
+        self.touched = Instant::now();
+        if self.black == Some(player) {
+            self.black = None;
+        }
+        if self.white == Some(player) {
+            self.white = None;
+        }
+    }
+    fn replace(&mut self, from: PlayerId, to: PlayerId) {
+        if self.black == Some(from) {
+            self.black = Some(to);
+        }
+        if self.white == Some(from) {
+            self.white = Some(to);
+        }
+    }
+    fn color_of(&self, player: PlayerId) -> Option<BorW> {
+        if self.black == Some(player) {
+            Some(BorW::Black)
+        } else if self.white == Some(player) {
+            Some(BorW::White)
+        } else {
+            None
+        }
+    }
+}
+
+/// The games as `addr` sees them; `your` is never set without `addr`.
+fn get_game_summary_list(server_data: &ServerData, addr: Option<SocketAddr>) -> Vec<GameSummary> {
     let mut gs = server_data
         .games
         .iter()
-        .map(|(k, _)| GameSummary {
+        .map(|(k, v)| GameSummary {
             id: *k,
+            state: v.state,
             members: server_data
                 .connections
                 .iter()
                 .filter(|(_, v)| v.game_id == Some(*k))
                 .count() as u32,
-            your: server_data.connections.get(addr).unwrap().game_id == Some(*k),
+            your: addr
+                .is_some_and(|x| server_data.connections.get(&x).unwrap().game_id == Some(*k)),
+            players: get_players(server_data, *k),
         })
         .collect::<Vec<GameSummary>>();
     gs.sort_by_key(|k| k.id);
     gs
 }
 
-fn add_new_game(games: &mut HashMap<GameID, ReversiData>) -> Result<GameID, ()> {
-    for i in 1..u32::MAX {
-        if let None = games.get(&(GameID(i))) {
-            games.insert(GameID(i), ReversiData::new());
-            return Ok(GameID(i));
-        }
+/// Nickname of a seat holder; `None` once their session has expired.
+fn player_name(server_data: &ServerData, player: PlayerId) -> Option<String> {
+    match player {
+        PlayerId::Session(x) => server_data.sessions.get(&x).cloned(),
+        PlayerId::Guest(x) => server_data.connections.get(&x).map(|x| x.nickname.clone()),
+        PlayerId::Computer => Some(COMPUTER_NAME.to_string()),
     }
-    return Err(());
 }
 
-fn handle_sessionlist(addr: SocketAddr, server_data: &ServerData) {
-    send_message(
-        &server_data.connections.get(&addr).unwrap().sender,
-        &ServerMessage::GameList(get_game_summary_list(server_data, &addr)),
-    );
+fn get_players(server_data: &ServerData, game_id: GameID) -> Players {
+    let game = match server_data.games.get(&game_id) {
+        Some(x) => x,
+        None => return Players::default(),
+    };
+    let nickname = |player: Option<PlayerId>| player_name(server_data, player?);
+    let mut spectators = server_data
+        .connections
+        .iter()
+        .filter(|(k, v)| v.game_id == Some(game_id) && game.color_of(v.player_id(**k)).is_none())
+        .map(|(_, v)| v.nickname.clone())
+        .collect::<Vec<String>>();
+    spectators.sort();
+    Players {
+        black: nickname(game.black),
+        white: nickname(game.white),
+        spectators,
+    }
 }
 
-fn handle_creategame(addr: SocketAddr, server_data: &mut ServerData) {
-    let new_game_id = add_new_game(&mut server_data.games).unwrap();
-    server_data.connections.get_mut(&addr).unwrap().game_id = Some(new_game_id);
-    send_message(
-        &server_data.connections.get(&addr).unwrap().sender,
-        &ServerMessage::View((server_data.games.get(&new_game_id).unwrap()).to_owned()),
-    );
+fn broadcast_game_list(server_data: &ServerData) {
     server_data.connections.iter().for_each(|(addr, conn)| {
         send_message(
             &conn.sender,
-            &ServerMessage::GameList(get_game_summary_list(server_data, &addr)),
+            &ServerMessage::GameList(get_game_summary_list(server_data, Some(*addr))),
         )
     });
 }
 
-fn handle_selectgame(addr: SocketAddr, server_data: &mut ServerData, game_id: GameID) {
-    server_data.connections.get_mut(&addr).unwrap().game_id = Some(game_id);
+fn broadcast_players(server_data: &ServerData, game_id: GameID) {
+    let message = ServerMessage::Players(get_players(server_data, game_id));
+    server_data
+        .connections
+        .values()
+        .filter(|x| x.game_id == Some(game_id))
+        .for_each(|x| send_message(&x.sender, &message));
+}
+
+fn broadcast_to_game(server_data: &ServerData, game_id: GameID, message: &ServerMessage) {
+    server_data
+        .connections
+        .values()
+        .filter(|x| x.game_id == Some(game_id))
+        .for_each(|x| send_message(&x.sender, message));
+}
+
+/// Sends the whole game to its members after a change that is not a move,
+/// which clients cannot apply as an update.
+fn broadcast_view(server_data: &mut ServerData, game_id: GameID) {
+    let game = server_data.games.get_mut(&game_id).unwrap();
+    game.seq += 1;
+    let message = game.view(Instant::now());
+    broadcast_to_game(server_data, game_id, &message);
+}
+
+fn add_new_game(server_data: &mut ServerData) -> Result<GameID, ()> {
+    if server_data.games.len() >= server_data.max_games {
+        return Err(());
+    }
+    let game_id = GameID(server_data.next_game_id);
+    server_data.next_game_id += 1;
+    server_data.games.insert(game_id, Game::new());
+    Ok(game_id)
+}
+
+fn current_game(addr: SocketAddr, server_data: &ServerData) -> Result<GameID, ClientError> {
+    server_data
+        .connections
+        .get(&addr)
+        .and_then(|x| x.game_id)
+        .ok_or_else(|| ClientError::new(ErrorCode::NotInGame, "you have not joined a game"))
+}
+
+fn unknown_game(game_id: GameID) -> ClientError {
+    ClientError::new(
+        ErrorCode::UnknownGame,
+        format!("game {} does not exist", game_id.0),
+    )
+}
+
+fn handle_sessionlist(addr: SocketAddr, server_data: &ServerData) -> Result<(), ClientError> {
     send_message(
         &server_data.connections.get(&addr).unwrap().sender,
-        &ServerMessage::View((*server_data.games.get(&game_id).unwrap()).to_owned()),
+        &ServerMessage::GameList(get_game_summary_list(server_data, Some(addr))),
     );
-    server_data.connections.iter().for_each(|(addr, conn)| {
-        send_message(
-            &conn.sender,
-            &ServerMessage::GameList(get_game_summary_list(server_data, &addr)),
-        )
+    Ok(())
+}
+
+/// Returns the game that was left, whose players have changed. Players keep
+/// their seat once the game has started and can come back to it.
+fn leave_game(addr: SocketAddr, server_data: &mut ServerData) -> Option<GameID> {
+    let connection = server_data.connections.get_mut(&addr).unwrap();
+    let player = connection.player_id(addr);
+    let game_id = connection.game_id.take();
+    if let Some(game) = game_id.and_then(|x| server_data.games.get_mut(&x)) {
+        if game.state == GameState::Waiting {
+            game.leave(player);
+        }
+    }
+    game_id
+}
+
+/// Tells everyone about games whose members or seats have changed.
+fn broadcast_seats(server_data: &ServerData, mut changed: Vec<GameID>) {
+    changed.sort_by_key(|x| x.0);
+    changed.dedup();
+    for game_id in &changed {
+        broadcast_players(server_data, *game_id);
+    }
+    if !changed.is_empty() {
+        broadcast_game_list(server_data);
+    }
+}
+
+/// Frees every seat `player` holds and returns the games concerned.
+fn vacate(server_data: &mut ServerData, player: PlayerId) -> Vec<GameID> {
+    server_data
+        .games
+        .iter_mut()
+        .filter(|(_, x)| x.color_of(player).is_some())
+        .map(|(k, x)| {
+            x.leave(player);
+            *k
+        })
+        .collect()
+}
+
+/// Drops a closed connection. A logged in player keeps their seat and is
+/// returned so that the caller can expire the session after the grace
+/// period.
+fn disconnect(addr: SocketAddr, server_data: &mut ServerData) -> Option<(SessionToken, Instant)> {
+    leave_queue(addr, server_data);
+    let connection = server_data.connections.remove(&addr)?;
+    let away = connection.token.map(|token| {
+        let since = Instant::now();
+        server_data.away.insert(token, (connection.game_id, since));
+        (token, since)
     });
+    let mut changed = match away {
+        Some(_) => Vec::new(),
+        None => vacate(server_data, PlayerId::Guest(addr)),
+    };
+    changed.extend(connection.game_id);
+    broadcast_seats(server_data, changed);
+    away
+}
+
+/// Frees the seats of a session that did not come back in time. Rated
+/// sessions are kept so that they can still be resumed.
+fn expire_session(server_data: &mut ServerData, token: SessionToken, since: Instant) {
+    if server_data.away.get(&token).map(|x| x.1) != Some(since) {
+        return;
+    }
+    let (game_id, _) = server_data.away.remove(&token).unwrap();
+    if !server_data.ratings.contains_key(&token) {
+        server_data.sessions.remove(&token);
+    }
+    let mut changed = vacate(server_data, PlayerId::Session(token));
+    changed.extend(game_id);
+    broadcast_seats(server_data, changed);
+}
+
+fn send_scrollback(addr: SocketAddr, server_data: &ServerData) {
+    let connection = server_data.connections.get(&addr).unwrap();
+    if let Some(chat) = server_data.chats.get(&connection.game_id) {
+        chat.iter()
+            .for_each(|x| send_message(&connection.sender, x));
+    }
+}
+
+fn join_game(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    game_id: GameID,
+) -> Result<(), ClientError> {
+    if !server_data.games.contains_key(&game_id) {
+        return Err(unknown_game(game_id));
+    }
+    // Picking a game by hand ends the search for one.
+    if server_data.queue.iter().any(|x| x.addr == addr) {
+        leave_queue(addr, server_data);
+        send_message(
+            &server_data.connections.get(&addr).unwrap().sender,
+            &ServerMessage::SearchCancelled,
+        );
+    }
+    if let Some(left) = leave_game(addr, server_data) {
+        broadcast_players(server_data, left);
+    }
+    server_data.connections.get_mut(&addr).unwrap().game_id = Some(game_id);
+    let player = server_data.connections.get(&addr).unwrap().player_id(addr);
+    let game = server_data.games.get_mut(&game_id).unwrap();
+    let role = game.join(player);
+    let now = Instant::now();
+    game.start(now);
+    let sender = &server_data.connections.get(&addr).unwrap().sender;
+    send_message(sender, &ServerMessage::Role(role));
+    send_message(sender, &game.view(now));
+    send_scrollback(addr, server_data);
+    broadcast_players(server_data, game_id);
+    Ok(())
+}
+
+fn handle_creategame(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    options: GameOptions,
+) -> Result<(), ClientError> {
+    if let Opponent::Computer { level } = options.opponent {
+        if !(1..=MAX_COMPUTER_LEVEL).contains(&level) {
+            return Err(ClientError::new(
+                ErrorCode::InvalidOptions,
+                format!("levels go from 1 to {}", MAX_COMPUTER_LEVEL),
+            ));
+        }
+    }
+    if let TimeControl::ByoYomi { period_ms: 0, .. } = options.time_control {
+        return Err(ClientError::new(
+            ErrorCode::InvalidOptions,
+            "byo-yomi periods cannot be empty",
+        ));
+    }
+    let new_game_id = add_new_game(server_data)
+        .map_err(|_| ClientError::new(ErrorCode::TooManyGames, "no more games can be created"))?;
+    let game = server_data.games.get_mut(&new_game_id).unwrap();
+    game.set_time_control(options.time_control);
+    if let Opponent::Computer { level } = options.opponent {
+        game.white = Some(PlayerId::Computer);
+        game.level = level;
+    }
+    server_data.save_game(new_game_id);
+    join_game(addr, server_data, new_game_id)?;
+    broadcast_game_list(server_data);
+    Ok(())
 }
 
-fn handle_put(addr: SocketAddr, server_data: &mut ServerData, position: Position) {
-    let gameid = server_data.connections.get(&addr).unwrap().game_id.unwrap();
-    server_data.games.get_mut(&gameid).unwrap().cursor = position;
-    try_put(server_data.games.get_mut(&gameid).unwrap());
+fn handle_selectgame(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    game_id: GameID,
+) -> Result<(), ClientError> {
+    join_game(addr, server_data, game_id)?;
+    broadcast_game_list(server_data);
+    Ok(())
+}
+
+fn handle_put(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    position: Position,
+) -> Result<(), ClientError> {
+    let gameid = current_game(addr, server_data)?;
+    let now = Instant::now();
+    check_flags(server_data, now);
+    let player = server_data.connections.get(&addr).unwrap().player_id(addr);
+    let game = server_data
+        .games
+        .get_mut(&gameid)
+        .ok_or_else(|| unknown_game(gameid))?;
+    if game.is_over() {
+        return Err(ClientError::new(ErrorCode::IllegalMove, "the game is over"));
+    }
+    if game.state != GameState::Playing {
+        return Err(ClientError::new(
+            ErrorCode::NotAllowed,
+            "the game has not started",
+        ));
+    }
+    if game.color_of(player) != Some(game.data.turn) {
+        return Err(ClientError::new(
+            ErrorCode::NotYourTurn,
+            "it is not your turn",
+        ));
+    }
+    let update = game.play(position, now)?;
+    play_computer(server_data, gameid);
+    server_data.save_game(gameid);
+    broadcast_to_game(server_data, gameid, &update);
     server_data
+        .metrics
+        .observe_move(Mover::Human, now.elapsed());
+    finish_game(server_data, gameid);
+    Ok(())
+}
+
+fn handle_resync(addr: SocketAddr, server_data: &ServerData) -> Result<(), ClientError> {
+    let gameid = current_game(addr, server_data)?;
+    let game = server_data
+        .games
+        .get(&gameid)
+        .ok_or_else(|| unknown_game(gameid))?;
+    send_message(
+        &server_data.connections.get(&addr).unwrap().sender,
+        &game.view(Instant::now()),
+    );
+    Ok(())
+}
+
+fn handle_login(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    nickname: String,
+) -> Result<(), ClientError> {
+    let nickname = nickname.trim().to_string();
+    let length = nickname.chars().count();
+    if length == 0 || length > MAX_NICKNAME_LENGTH || nickname.chars().any(char::is_control) {
+        return Err(ClientError::new(
+            ErrorCode::InvalidNickname,
+            format!(
+                "nicknames must be 1 to {} printable characters",
+                MAX_NICKNAME_LENGTH
+            ),
+        ));
+    }
+    let own_token = server_data.connections.get(&addr).unwrap().token;
+    let taken = |x: &String| x.to_lowercase() == nickname.to_lowercase();
+    // Sessions of players who are away keep their nickname until they expire.
+    if server_data
         .connections
         .iter()
-        .filter(|&(_, v)| v.game_id == Some(gameid))
-        .for_each(|(_, v)| {
-            send_message(
-                &v.sender,
-                &ServerMessage::View((*server_data.games.get(&gameid).unwrap()).to_owned()),
-            );
-        });
+        .any(|(k, v)| *k != addr && taken(&v.nickname))
+        || server_data
+            .sessions
+            .iter()
+            .any(|(k, v)| Some(*k) != own_token && taken(v))
+    {
+        return Err(ClientError::new(
+            ErrorCode::NicknameTaken,
+            format!("{} is already in use", nickname),
+        ));
+    }
+    let connection = server_data.connections.get_mut(&addr).unwrap();
+    let guest = connection.player_id(addr);
+    let token = *connection
+        .token
+        .get_or_insert_with(|| SessionToken(uuid::Uuid::new_v4()));
+    connection.nickname = nickname.clone();
+    let game_id = connection.game_id;
+    if let Some(game) = game_id.and_then(|x| server_data.games.get_mut(&x)) {
+        game.replace(guest, PlayerId::Session(token));
+    }
+    let connection = server_data.connections.get(&addr).unwrap();
+    send_message(
+        &connection.sender,
+        &ServerMessage::Welcome {
+            nickname: nickname.clone(),
+            token,
+        },
+    );
+    server_data.sessions.insert(token, nickname);
+    if server_data.ratings.contains_key(&token) {
+        save_ratings(server_data);
+    }
+    if let Some(x) = game_id {
+        broadcast_players(server_data, x);
+    }
+    broadcast_game_list(server_data);
+    Ok(())
 }
 
-fn handle_reset(addr: SocketAddr, server_data: &mut ServerData) {
-    let gameid = match server_data.connections.get(&addr).unwrap().game_id {
-        Some(x) => x,
-        None => return,
+fn handle_resume(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    token: SessionToken,
+) -> Result<(), ClientError> {
+    let nickname =
+        server_data.sessions.get(&token).cloned().ok_or_else(|| {
+            ClientError::new(ErrorCode::UnknownSession, "the session has expired")
+        })?;
+    // A connection still holding the session is a stale one the server has
+    // not noticed closing yet; it drops back to being a guest.
+    let stale = server_data
+        .connections
+        .iter()
+        .find(|(k, v)| **k != addr && v.token == Some(token))
+        .map(|(k, _)| *k);
+    let game_id = match stale {
+        Some(x) => {
+            let connection = server_data.connections.get_mut(&x).unwrap();
+            connection.token = None;
+            connection.nickname = format!("guest{}", x.port());
+            connection.game_id.take()
+        }
+        None => server_data.away.remove(&token).and_then(|x| x.0),
+    };
+    if let Some(left) = leave_game(addr, server_data) {
+        broadcast_players(server_data, left);
+    }
+    let connection = server_data.connections.get_mut(&addr).unwrap();
+    connection.token = Some(token);
+    connection.nickname = nickname.clone();
+    send_message(
+        &connection.sender,
+        &ServerMessage::Welcome { nickname, token },
+    );
+    if let Some(x) = game_id.filter(|x| server_data.games.contains_key(x)) {
+        join_game(addr, server_data, x)?;
+    }
+    broadcast_game_list(server_data);
+    Ok(())
+}
+
+fn handle_chat(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    text: String,
+) -> Result<(), ClientError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(());
+    }
+    if text.chars().count() > MAX_CHAT_LENGTH {
+        return Err(ClientError::new(
+            ErrorCode::ChatTooLong,
+            format!(
+                "chat messages are limited to {} characters",
+                MAX_CHAT_LENGTH
+            ),
+        ));
+    }
+    let connection = server_data.connections.get(&addr).unwrap();
+    let room = connection.game_id;
+    let message = ServerMessage::ChatMessage {
+        from: connection.nickname.clone(),
+        text: text.to_string(),
+        timestamp: now_millis(),
     };
-    *server_data.games.get_mut(&gameid).unwrap() = ReversiData::new();
     server_data
         .connections
-        .iter()
-        .filter(|&(_, v)| v.game_id == Some(gameid))
-        .for_each(|(_, v)| {
-            send_message(
-                &v.sender,
-                &ServerMessage::View((*server_data.games.get(&gameid).unwrap()).to_owned()),
-            );
-        });
+        .values()
+        .filter(|x| x.game_id == room)
+        .for_each(|x| send_message(&x.sender, &message));
+    let chat = server_data.chats.entry(room).or_default();
+    chat.push_back(message);
+    if chat.len() > CHAT_SCROLLBACK {
+        chat.pop_front();
+    }
+    Ok(())
+}
+
+fn send_error(addr: SocketAddr, server_data: &mut ServerData, error: ClientError) {
+    debug!(code = ?error.code, error = %error.message, "client error");
+    server_data.metrics.count_error(error.code);
+    if let Some(connection) = server_data.connections.get(&addr) {
+        send_message(
+            &connection.sender,
+            &ServerMessage::Error {
+                code: error.code,
+                message: error.message,
+            },
+        );
+    }
 }
 
 fn handle_message(addr: SocketAddr, server_data: &mut ServerData, message: ClientMessage) {
-    match message {
+    let connection = server_data.connections.get(&addr).unwrap();
+    let greeted = connection.greeted;
+    let _span = info_span!(
+        "message",
+        kind = message.kind(),
+        game_id = connection.game_id.map(|x| x.0)
+    )
+    .entered();
+    debug!("received");
+    server_data.metrics.count_message(&message);
+    if !greeted && !matches!(message, ClientMessage::Hello { .. }) {
+        reject(
+            addr,
+            server_data,
+            "the first message has to be Hello".to_string(),
+        );
+        return;
+    }
+    let result = match message {
+        ClientMessage::Hello {
+            protocol_version,
+            client_name,
+            encodings,
+        } => handle_hello(addr, server_data, protocol_version, client_name, encodings),
         ClientMessage::SessionList => handle_sessionlist(addr, server_data),
-        ClientMessage::CreateGame => handle_creategame(addr, server_data),
+        ClientMessage::CreateGame(x) => handle_creategame(addr, server_data, x),
         ClientMessage::SelectGame(x) => handle_selectgame(addr, server_data, x),
+        ClientMessage::Login { nickname } => handle_login(addr, server_data, nickname),
+        ClientMessage::Resume(x) => handle_resume(addr, server_data, x),
+        ClientMessage::FindMatch => handle_findmatch(addr, server_data),
+        ClientMessage::CancelMatch => handle_cancelmatch(addr, server_data),
+        ClientMessage::Leaderboard => handle_leaderboard(addr, server_data),
         ClientMessage::Put(x) => handle_put(addr, server_data, x),
-        ClientMessage::Reset => handle_reset(addr, server_data),
+        ClientMessage::Resync => handle_resync(addr, server_data),
+        ClientMessage::Reset => handle_offer(addr, server_data, Proposal::Reset),
+        ClientMessage::Resign => handle_resign(addr, server_data),
+        ClientMessage::Offer(x) => handle_offer(addr, server_data, x),
+        ClientMessage::Accept(x) => handle_accept(addr, server_data, x),
+        ClientMessage::Decline(x) => handle_decline(addr, server_data, x),
+        ClientMessage::Chat(x) => handle_chat(addr, server_data, x),
+    };
+    if let Err(e) = result {
+        send_error(addr, server_data, e);
     }
 }
 
-async fn handle_connection(
+/// Reads a text frame as JSON and a binary frame in `encoding`.
+fn parse_message(msg: Message, encoding: Encoding) -> Option<Result<ClientMessage, ClientError>> {
+    let malformed = |message: String| ClientError::new(ErrorCode::MalformedMessage, message);
+    match msg {
+        Message::Text(x) => Some(serde_json::from_str(&x).map_err(|e| malformed(e.to_string()))),
+        Message::Binary(_) if !encoding.is_binary() => Some(Err(malformed(
+            "binary messages need an encoding agreed on in Hello".to_string(),
+        ))),
+        Message::Binary(x) => Some(encoding.decode(&x).map_err(|e| malformed(e.to_string()))),
+        _ => None,
+    }
+}
+
+/// Terminates TLS when it is configured and serves the connection.
+async fn accept_connection(
     raw_stream: TcpStream,
     addr: SocketAddr,
+    tls: Option<TlsAcceptor>,
     server_data: Arc<Mutex<ServerData>>,
+    permit: OwnedSemaphorePermit,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (outgoing, incoming) = tokio_tungstenite::accept_async(raw_stream).await?.split();
-    println!("WebSocket connection established: {}", addr);
-    let (tx, rx) = unbounded();
-    let connection = ConnectionData {
-        game_id: None,
-        sender: tx,
+    match tls {
+        Some(x) => {
+            let stream = tokio::time::timeout(HEAD_TIMEOUT, x.accept(raw_stream)).await??;
+            handle_connection(stream, addr, server_data, permit).await
+        }
+        None => handle_connection(raw_stream, addr, server_data, permit).await,
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    raw_stream: S,
+    addr: SocketAddr,
+    server_data: Arc<Mutex<ServerData>>,
+    _permit: OwnedSemaphorePermit,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Plain HTTP requests share the port with the WebSocket.
+    let stream = match tokio::time::timeout(HEAD_TIMEOUT, sniff(raw_stream)).await?? {
+        Request::WebSocket(x) => x,
+        Request::Http {
+            stream,
+            method,
+            path,
+        } => return Ok(respond(stream, &server_data, &method, &path).await?),
     };
-    server_data.lock().unwrap().add_connectin(addr, connection);
+    let (outgoing, incoming) = tokio_tungstenite::accept_async(stream).await?.split();
+    info!("websocket connected");
+    let (tx, rx) = unbounded();
+    let connection = ConnectionData::new(addr, tx);
+    {
+        let mut server_data = lock(&server_data);
+        server_data.add_connectin(addr, connection);
+    }
     let broadcast_incoming = incoming.try_for_each(|msg| async {
-        let client_message = serde_json::from_str(&msg.into_text()?)
-            .map_err(|_| tungstenite::Error::ConnectionClosed)?;
-        println!("client message reveive");
-        handle_message(addr, &mut *server_data.lock().unwrap(), client_message);
+        let mut server_data = lock(&server_data);
+        let encoding = server_data.connections.get(&addr).unwrap().sender.encoding;
+        let client_message = match parse_message(msg, encoding) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        match client_message {
+            Ok(x) => handle_message(addr, &mut server_data, x),
+            // Most likely a client from before the handshake.
+            Err(e) if !server_data.connections.get(&addr).unwrap().greeted => {
+                reject(addr, &server_data, e.message)
+            }
+            Err(e) => send_error(addr, &mut server_data, e),
+        }
         Ok(())
     });
     let receive_from_others = rx.map(Ok).forward(outgoing);
     pin_mut!(broadcast_incoming, receive_from_others);
     future::select(broadcast_incoming, receive_from_others).await;
-    println!("{} disconnected", &addr);
-    server_data.lock().unwrap().connections.remove(&addr);
+    info!("disconnected");
+    let away = disconnect(addr, &mut lock(&server_data));
+    if let Some((token, since)) = away {
+        schedule_expiry(server_data, token, since);
+    }
     Ok(())
 }
 
+/// Expires the session once `reconnect_grace` has passed, unless it has been
+/// resumed by then.
+fn schedule_expiry(server_data: Arc<Mutex<ServerData>>, token: SessionToken, since: Instant) {
+    let grace = lock(&server_data).reconnect_grace;
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        expire_session(&mut lock(&server_data), token, since);
+    });
+}
+
 #[tokio::main]
-async fn main() -> Result<(), IoError> {
-    let server_data = Arc::new(Mutex::new(ServerData::new()));
-    while let Ok((stream, addr)) = TcpListener::bind(&"127.0.0.1:9001".to_string())
-        .await?
-        .accept()
-        .await
-    {
-        tokio::spawn(handle_connection(stream, addr, server_data.clone()));
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level)
+        .init();
+    let store: Box<dyn GameStore> = match &config.storage_dir {
+        Some(dir) => Box::new(FileStore::open(dir.clone())?),
+        None => Box::new(MemoryStore),
+    };
+    let mut server_data = ServerData::new(config.max_games, store);
+    server_data.reconnect_grace = config.reconnect_grace;
+    server_data.match_timeout = config.match_timeout;
+    server_data.game_idle_timeout = config.game_idle_timeout;
+    server_data.static_dir = config.static_dir.clone();
+    server_data.restore()?;
+    info!(games = server_data.games.len(), "restored");
+    let computer_rx = server_data.computer_rx.take().unwrap();
+    let engine = Engine {
+        weights: match &config.weights {
+            Some(x) => Some(reversi_ai::Weights::load(x)?),
+            None => None,
+        },
+    };
+    let away = server_data
+        .away
+        .iter()
+        .map(|(k, v)| (*k, v.1))
+        .collect::<Vec<_>>();
+    let server_data = Arc::new(Mutex::new(server_data));
+    for (token, since) in away {
+        schedule_expiry(server_data.clone(), token, since);
+    }
+    tokio::spawn(computer_worker(
+        server_data.clone(),
+        computer_rx,
+        Arc::new(engine),
+    ));
+    let matcher_data = server_data.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            match_players(&mut lock(&matcher_data), Instant::now());
+        }
+    });
+    let sweeper_data = server_data.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            sweep_games(&mut lock(&sweeper_data), Instant::now());
+        }
+    });
+    let clock_data = server_data.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        loop {
+            interval.tick().await;
+            check_flags(&mut lock(&clock_data), Instant::now());
+        }
+    });
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+        _ => None,
+    };
+    let connection_limit = Arc::new(Semaphore::new(config.max_connections));
+    let listener = TcpListener::bind(config.bind_address()).await?;
+    info!(
+        address = %listener.local_addr()?,
+        tls = tls.is_some(),
+        "listening"
+    );
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(error = %e, "accept failed");
+                continue;
+            }
+        };
+        let permit = match connection_limit.clone().try_acquire_owned() {
+            Ok(x) => x,
+            Err(_) => {
+                warn!(%addr, "connection limit reached, refusing");
+                continue;
+            }
+        };
+        let connection = accept_connection(stream, addr, tls.clone(), server_data.clone(), permit);
+        let server_data = server_data.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = connection.await {
+                    info!(error = %e, "connection failed");
+                    lock(&server_data).metrics.count_connection_error();
+                }
+            }
+            .instrument(info_span!("connection", %addr)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_channel::mpsc::UnboundedReceiver;
+
+    fn connect(
+        server_data: &mut ServerData,
+        port: u16,
+    ) -> (SocketAddr, UnboundedReceiver<Message>) {
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        let (tx, mut rx) = unbounded();
+        server_data.add_connectin(addr, ConnectionData::new(addr, tx));
+        handle_message(
+            addr,
+            server_data,
+            ClientMessage::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_name: "test".to_string(),
+                encodings: Vec::new(),
+            },
+        );
+        received(&mut rx);
+        (addr, rx)
+    }
+
+    fn received(rx: &mut UnboundedReceiver<Message>) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        while let Ok(Some(x)) = rx.try_next() {
+            if let Message::Text(x) = x {
+                messages.push(serde_json::from_str(&x).unwrap());
+            }
+        }
+        messages
+    }
+
+    fn error_code(messages: &[ServerMessage]) -> Option<ErrorCode> {
+        messages.iter().find_map(|x| match x {
+            ServerMessage::Error { code, .. } => Some(*code),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn handshake_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let hello = |protocol_version| ClientMessage::Hello {
+            protocol_version,
+            client_name: "test".to_string(),
+            encodings: Vec::new(),
+        };
+        let mut connect_raw = |port| {
+            let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+            let (tx, rx) = unbounded();
+            server_data.add_connectin(addr, ConnectionData::new(addr, tx));
+            (addr, rx)
+        };
+        let (a, mut rx_a) = connect_raw(1);
+        let (b, mut rx_b) = connect_raw(2);
+        let (c, mut rx_c) = connect_raw(3);
+        handle_message(a, &mut server_data, hello(PROTOCOL_VERSION));
+        assert!(received(&mut rx_a).iter().any(|x| matches!(
+            x,
+            ServerMessage::Hello { features, .. } if features.contains(&Feature::Clocks)
+        )));
+        handle_message(b, &mut server_data, hello(PROTOCOL_VERSION + 1));
+        handle_message(c, &mut server_data, ClientMessage::SessionList);
+        for rx in [&mut rx_b, &mut rx_c] {
+            let mut messages = Vec::new();
+            while let Ok(Some(x)) = rx.try_next() {
+                messages.push(x);
+            }
+            assert!(messages.len() == 2 && matches!(messages[1], Message::Close(_)));
+            let reply = serde_json::from_str(messages[0].to_text().unwrap()).unwrap();
+            assert!(matches!(reply, ServerMessage::Rejected { .. }));
+        }
+        assert!(!server_data.connections.get(&b).unwrap().greeted);
+    }
+    #[test]
+    fn encoding_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let (tx, mut rx) = unbounded();
+        server_data.add_connectin(addr, ConnectionData::new(addr, tx));
+        assert!(matches!(
+            parse_message(Message::Binary(vec![0x80]), Encoding::Json),
+            Some(Err(ClientError {
+                code: ErrorCode::MalformedMessage,
+                ..
+            }))
+        ));
+        handle_message(
+            addr,
+            &mut server_data,
+            ClientMessage::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                client_name: "test".to_string(),
+                encodings: vec![Encoding::Cbor, Encoding::Json],
+            },
+        );
+        match rx.try_next() {
+            Ok(Some(Message::Text(x))) => assert!(matches!(
+                serde_json::from_str(&x).unwrap(),
+                ServerMessage::Hello {
+                    encoding: Encoding::Cbor,
+                    ..
+                }
+            )),
+            _ => panic!("the reply to Hello has to be JSON text"),
+        }
+        let bytes = Encoding::Cbor.encode(&ClientMessage::SessionList).unwrap();
+        let message = parse_message(Message::Binary(bytes), Encoding::Cbor);
+        handle_message(addr, &mut server_data, message.unwrap().ok().unwrap());
+        match rx.try_next() {
+            Ok(Some(Message::Binary(x))) => assert!(matches!(
+                Encoding::Cbor.decode(&x).unwrap(),
+                ServerMessage::GameList(_)
+            )),
+            _ => panic!("messages after Hello have to be binary"),
+        }
+    }
+    #[test]
+    fn error_reply_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let (a, mut rx) = connect(&mut server_data, 1);
+        handle_message(a, &mut server_data, ClientMessage::SelectGame(GameID(5)));
+        assert!(error_code(&received(&mut rx)) == Some(ErrorCode::UnknownGame));
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Put(Position::new(4, 2).unwrap()),
+        );
+        assert!(error_code(&received(&mut rx)) == Some(ErrorCode::NotInGame));
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        assert!(error_code(&received(&mut rx)).is_none());
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        assert!(error_code(&received(&mut rx)) == Some(ErrorCode::TooManyGames));
+        // Black has no opponent yet.
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Put(Position::new(4, 2).unwrap()),
+        );
+        assert!(error_code(&received(&mut rx)) == Some(ErrorCode::NotAllowed));
+        assert!(server_data.games[&GameID(1)].record.moves.is_empty());
+        let (b, mut rx_b) = connect(&mut server_data, 2);
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        handle_message(
+            b,
+            &mut server_data,
+            ClientMessage::Put(Position::new(4, 2).unwrap()),
+        );
+        assert!(error_code(&received(&mut rx_b)) == Some(ErrorCode::NotYourTurn));
+        received(&mut rx);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Put(Position::new(0, 0).unwrap()),
+        );
+        assert!(error_code(&received(&mut rx)) == Some(ErrorCode::IllegalMove));
+        match parse_message(Message::Text("{".to_string()), Encoding::Json) {
+            Some(Err(e)) => assert!(e.code == ErrorCode::MalformedMessage),
+            _ => panic!(),
+        }
+    }
+    #[test]
+    fn switch_game_test() {
+        let mut server_data = ServerData::new(2, Box::new(MemoryStore));
+        let (a, _rx_a) = connect(&mut server_data, 1);
+        let (b, mut rx_b) = connect(&mut server_data, 2);
+        let (c, mut rx_c) = connect(&mut server_data, 3);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        handle_message(
+            b,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        let game = server_data.games.get(&GameID(1)).unwrap();
+        assert!(game.state == GameState::Playing);
+        assert!(game.white == Some(PlayerId::Guest(b)));
+        handle_message(c, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        assert!(received(&mut rx_c)
+            .iter()
+            .any(|x| matches!(x, ServerMessage::Role(Role::Spectator))));
+        // b's own game has not started, so b does not keep its seat there.
+        received(&mut rx_b);
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        assert!(received(&mut rx_b)
+            .iter()
+            .any(|x| matches!(x, ServerMessage::Role(Role::Player(BorW::White)))));
+        assert!(server_data.games.get(&GameID(2)).unwrap().black.is_none());
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Put(Position::from_notation("e6").unwrap()),
+        );
+        handle_message(
+            b,
+            &mut server_data,
+            ClientMessage::Put(Position::from_notation("f4").unwrap()),
+        );
+        assert!(error_code(&received(&mut rx_b)).is_none());
+        // A guest's seats all go with its connection.
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(2)));
+        disconnect(b, &mut server_data);
+        assert!(server_data.games.get(&GameID(1)).unwrap().white.is_none());
+    }
+    #[test]
+    fn restore_test() {
+        let dir = std::env::temp_dir().join(format!("reversi_restore_{}", std::process::id()));
+        let mut server_data = ServerData::new(2, Box::new(FileStore::open(dir.clone()).unwrap()));
+        let (a, _rx_a) = connect(&mut server_data, 1);
+        let (b, _rx_b) = connect(&mut server_data, 2);
+        let (c, _rx_c) = connect(&mut server_data, 3);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Login {
+                nickname: "alice".to_string(),
+            },
+        );
+        let token = server_data.connections.get(&a).unwrap().token.unwrap();
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        handle_message(
+            c,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions {
+                opponent: Opponent::Computer { level: 2 },
+                ..Default::default()
+            }),
+        );
+        for (player, p) in [(a, "f5"), (b, "d6"), (a, "c3")] {
+            handle_message(
+                player,
+                &mut server_data,
+                ClientMessage::Put(Position::from_notation(p).unwrap()),
+            );
+        }
+        let mut restored = ServerData::new(2, Box::new(FileStore::open(dir.clone()).unwrap()));
+        restored.restore().unwrap();
+        let before = server_data.games.get(&GameID(1)).unwrap();
+        let after = restored.games.get(&GameID(1)).unwrap();
+        assert!(after.record.moves.len() == 3);
+        assert!(after.record == before.record);
+        assert!(after.data.field == before.data.field);
+        assert!(after.data.turn == before.data.turn);
+        // alice can resume her seat; the guest's is open to anyone.
+        assert!(after.state == GameState::Playing);
+        assert!(after.black == Some(PlayerId::Session(token)));
+        assert!(after.white.is_none());
+        assert!(restored.away.get(&token).unwrap().0 == Some(GameID(1)));
+        let computer = restored.games.get(&GameID(2)).unwrap();
+        assert!(computer.white == Some(PlayerId::Computer) && computer.level == 2);
+        assert!(computer.black.is_none());
+        let (d, mut rx_d) = connect(&mut restored, 4);
+        handle_message(d, &mut restored, ClientMessage::Resume(token));
+        assert!(received(&mut rx_d)
+            .iter()
+            .any(|x| matches!(x, ServerMessage::Role(Role::Player(BorW::Black)))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn chat_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, mut rx_b) = connect(&mut server_data, 2);
+        let chats = |messages: Vec<ServerMessage>| {
+            messages
+                .into_iter()
+                .filter_map(|x| match x {
+                    ServerMessage::ChatMessage { text, .. } => Some(text),
+                    _ => None,
+                })
+                .collect::<Vec<String>>()
+        };
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Chat(" hello ".to_string()),
+        );
+        assert!(chats(received(&mut rx_b)) == vec!["hello"]);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Chat("in game".to_string()),
+        );
+        assert!(chats(received(&mut rx_a)) == vec!["hello", "in game"]);
+        assert!(chats(received(&mut rx_b)).is_empty());
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        assert!(chats(received(&mut rx_b)) == vec!["in game"]);
+        handle_message(
+            b,
+            &mut server_data,
+            ClientMessage::Chat("x".repeat(MAX_CHAT_LENGTH + 1)),
+        );
+        assert!(error_code(&received(&mut rx_b)) == Some(ErrorCode::ChatTooLong));
+        assert!(chats(received(&mut rx_a)).is_empty());
+    }
+    #[test]
+    fn login_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, mut rx_b) = connect(&mut server_data, 2);
+        let (c, mut rx_c) = connect(&mut server_data, 3);
+        let login = |name: &str| ClientMessage::Login {
+            nickname: name.to_string(),
+        };
+        handle_message(a, &mut server_data, login(" alice "));
+        let token = received(&mut rx_a).iter().find_map(|x| match x {
+            ServerMessage::Welcome { nickname, token } if nickname == "alice" => Some(*token),
+            _ => None,
+        });
+        assert!(token.is_some());
+        handle_message(b, &mut server_data, login("ALICE"));
+        assert!(error_code(&received(&mut rx_b)) == Some(ErrorCode::NicknameTaken));
+        handle_message(b, &mut server_data, login(""));
+        assert!(error_code(&received(&mut rx_b)) == Some(ErrorCode::InvalidNickname));
+        handle_message(b, &mut server_data, login("bob"));
+        handle_message(c, &mut server_data, login("carol"));
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        handle_message(c, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        let players = received(&mut rx_a)
+            .into_iter()
+            .filter_map(|x| match x {
+                ServerMessage::Players(x) => Some(x),
+                _ => None,
+            })
+            .next_back()
+            .unwrap();
+        assert!(players.black.as_deref() == Some("alice"));
+        assert!(players.white.as_deref() == Some("bob"));
+        assert!(players.spectators == vec!["carol"]);
+        let list = received(&mut rx_c)
+            .into_iter()
+            .filter_map(|x| match x {
+                ServerMessage::GameList(x) => Some(x),
+                _ => None,
+            })
+            .next_back();
+        assert!(list.unwrap()[0].players == players);
+        handle_message(a, &mut server_data, login("alice"));
+        assert!(error_code(&received(&mut rx_a)).is_none());
+        assert!(server_data.connections.get(&a).unwrap().token == token);
+    }
+    #[test]
+    fn resume_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, mut rx_b) = connect(&mut server_data, 2);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Login {
+                nickname: "alice".to_string(),
+            },
+        );
+        let token = server_data.connections.get(&a).unwrap().token.unwrap();
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        received(&mut rx_a);
+        let since = disconnect(a, &mut server_data).unwrap().1;
+        let game = server_data.games.get(&GameID(1)).unwrap();
+        assert!(game.black == Some(PlayerId::Session(token)));
+        received(&mut rx_b);
+        let (c, mut rx_c) = connect(&mut server_data, 3);
+        let login = ClientMessage::Login {
+            nickname: "Alice".to_string(),
+        };
+        handle_message(c, &mut server_data, login.clone());
+        assert!(error_code(&received(&mut rx_c)) == Some(ErrorCode::NicknameTaken));
+        handle_message(c, &mut server_data, ClientMessage::Resume(token));
+        let messages = received(&mut rx_c);
+        assert!(messages
+            .iter()
+            .any(|x| matches!(x, ServerMessage::Role(Role::Player(BorW::Black)))));
+        assert!(messages
+            .iter()
+            .any(|x| matches!(x, ServerMessage::View { .. })));
+        handle_message(
+            c,
+            &mut server_data,
+            ClientMessage::Put(Position::new(4, 2).unwrap()),
+        );
+        assert!(error_code(&received(&mut rx_c)).is_none());
+        // The session came back, so the earlier expiry is void.
+        expire_session(&mut server_data, token, since);
+        assert!(server_data.sessions.contains_key(&token));
+        let since = disconnect(c, &mut server_data).unwrap().1;
+        expire_session(&mut server_data, token, since);
+        assert!(server_data.games.get(&GameID(1)).unwrap().black.is_none());
+        let (d, mut rx_d) = connect(&mut server_data, 4);
+        handle_message(d, &mut server_data, ClientMessage::Resume(token));
+        assert!(error_code(&received(&mut rx_d)) == Some(ErrorCode::UnknownSession));
+        handle_message(d, &mut server_data, login);
+        assert!(error_code(&received(&mut rx_d)).is_none());
+    }
+    #[test]
+    fn matchmaking_test() {
+        let mut server_data = ServerData::new(3, Box::new(MemoryStore));
+        let mut requests = server_data.computer_rx.take().unwrap();
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, _rx_b) = connect(&mut server_data, 2);
+        let (c, mut rx_c) = connect(&mut server_data, 3);
+        let mut tokens = Vec::new();
+        for (x, name) in [(a, "alice"), (c, "carol")] {
+            handle_message(
+                x,
+                &mut server_data,
+                ClientMessage::Login {
+                    nickname: name.to_string(),
+                },
+            );
+            tokens.push(server_data.connections.get(&x).unwrap().token.unwrap());
+        }
+        server_data.ratings.insert(tokens[0], 1900.0);
+        server_data.ratings.insert(tokens[1], 1850.0);
+        handle_message(a, &mut server_data, ClientMessage::FindMatch);
+        handle_message(b, &mut server_data, ClientMessage::FindMatch);
+        assert!(server_data.queue.len() == 2);
+        handle_message(c, &mut server_data, ClientMessage::FindMatch);
+        assert!(server_data.queue.len() == 1);
+        let found = |messages: Vec<ServerMessage>| {
+            messages.into_iter().find_map(|x| match x {
+                ServerMessage::MatchFound {
+                    game_id, opponent, ..
+                } => Some((game_id, opponent)),
+                _ => None,
+            })
+        };
+        let (game_id, opponent) = found(received(&mut rx_a)).unwrap();
+        assert!(opponent == "carol");
+        assert!(found(received(&mut rx_c)).unwrap().0 == game_id);
+        let game = server_data.games.get(&game_id).unwrap();
+        let seats = [game.black, game.white];
+        assert!(seats.contains(&Some(PlayerId::Session(tokens[0]))));
+        assert!(seats.contains(&Some(PlayerId::Session(tokens[1]))));
+        handle_message(b, &mut server_data, ClientMessage::CancelMatch);
+        assert!(server_data.queue.is_empty());
+        handle_message(b, &mut server_data, ClientMessage::FindMatch);
+        let later = Instant::now() + server_data.match_timeout;
+        match_players(&mut server_data, later);
+        computer::run_pending(&mut server_data, &mut requests, &Engine::default());
+        let game_id = server_data.connections.get(&b).unwrap().game_id.unwrap();
+        let game = server_data.games.get(&game_id).unwrap();
+        let computer = game.color_of(PlayerId::Computer).unwrap();
+        assert!(game.color_of(PlayerId::Guest(b)) == Some(get_another_color(computer)));
+        assert!(game.data.turn != computer);
+        // Selecting a game while searching leaves the queue.
+        let (d, mut rx_d) = connect(&mut server_data, 4);
+        handle_message(d, &mut server_data, ClientMessage::FindMatch);
+        handle_message(d, &mut server_data, ClientMessage::SelectGame(game_id));
+        assert!(server_data.queue.is_empty());
+        assert!(received(&mut rx_d)
+            .iter()
+            .any(|x| matches!(x, ServerMessage::SearchCancelled)));
+        let (e, _rx_e) = connect(&mut server_data, 5);
+        handle_message(e, &mut server_data, ClientMessage::FindMatch);
+        let later = Instant::now() + server_data.match_timeout;
+        match_players(&mut server_data, later);
+        assert!(server_data.connections.get(&d).unwrap().game_id == Some(game_id));
+    }
+    #[test]
+    fn rating_test() {
+        let (black, white) = ratings::elo_update(1500.0, 1500.0, Some(BorW::Black));
+        assert!(black == 1516.0 && white == 1484.0);
+        let (black, white) = ratings::elo_update(1600.0, 1400.0, None);
+        assert!(black < 1600.0 && white > 1400.0);
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, _rx_b) = connect(&mut server_data, 2);
+        for (x, name) in [(a, "alice"), (b, "bob")] {
+            handle_message(
+                x,
+                &mut server_data,
+                ClientMessage::Login {
+                    nickname: name.to_string(),
+                },
+            );
+        }
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        // The shortest possible game: Black wipes out White in nine moves.
+        let moves = ["e6", "f4", "e3", "f6", "g5", "d6", "e7", "f5", "c5"];
+        for (i, p) in moves.iter().enumerate() {
+            let player = if i % 2 == 0 { a } else { b };
+            handle_message(
+                player,
+                &mut server_data,
+                ClientMessage::Put(Position::from_notation(p).unwrap()),
+            );
+        }
+        let changes = received(&mut rx_a).into_iter().find_map(|x| match x {
+            ServerMessage::GameOver {
+                winner,
+                rating_changes,
+                ..
+            } => Some((winner, rating_changes)),
+            _ => None,
+        });
+        let (winner, changes) = changes.unwrap();
+        assert!(winner == Some(BorW::Black));
+        assert!(changes[0].nickname == "alice" && changes[0].new == 1516);
+        assert!(changes[1].nickname == "bob" && changes[1].new == 1484);
+        handle_message(b, &mut server_data, ClientMessage::Leaderboard);
+        let board = received(&mut rx_a);
+        assert!(board.is_empty());
+        handle_message(a, &mut server_data, ClientMessage::Leaderboard);
+        let board = received(&mut rx_a).into_iter().find_map(|x| match x {
+            ServerMessage::Leaderboard { top, you } => Some((top, you)),
+            _ => None,
+        });
+        let (top, you) = board.unwrap();
+        assert!(top.len() == 2 && top[0].nickname == "alice");
+        assert!(you.unwrap().rank == 1);
+        // The rating stays with alice's session after it has expired.
+        let token = server_data.connections.get(&a).unwrap().token.unwrap();
+        let since = disconnect(a, &mut server_data).unwrap().1;
+        expire_session(&mut server_data, token, since);
+        let (c, mut rx_c) = connect(&mut server_data, 3);
+        handle_message(
+            c,
+            &mut server_data,
+            ClientMessage::Login {
+                nickname: "ALICE".to_string(),
+            },
+        );
+        assert!(error_code(&received(&mut rx_c)) == Some(ErrorCode::NicknameTaken));
+        handle_message(c, &mut server_data, ClientMessage::Resume(token));
+        assert!(error_code(&received(&mut rx_c)).is_none());
+        assert!(ratings::rating(&server_data, token) == 1516.0);
+    }
+    #[test]
+    fn computer_test() {
+        let mut server_data = ServerData::new(2, Box::new(MemoryStore));
+        let mut requests = server_data.computer_rx.take().unwrap();
+        let engine = Engine::default();
+        let (a, mut rx) = connect(&mut server_data, 1);
+        let create = |level| {
+            ClientMessage::CreateGame(GameOptions {
+                opponent: Opponent::Computer { level },
+                ..Default::default()
+            })
+        };
+        handle_message(a, &mut server_data, create(MAX_COMPUTER_LEVEL + 1));
+        assert!(error_code(&received(&mut rx)) == Some(ErrorCode::InvalidOptions));
+        for level in 1..=MAX_COMPUTER_LEVEL {
+            let field = Field::new();
+            assert!(engine.choose(&field, BorW::Black, level).is_some());
+        }
+        handle_message(a, &mut server_data, create(2));
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Put(Position::from_notation("f5").unwrap()),
+        );
+        let game = server_data.games.get(&GameID(1)).unwrap();
+        assert!(game.data.turn == BorW::White);
+        computer::run_pending(&mut server_data, &mut requests, &engine);
+        let game = server_data.games.get(&GameID(1)).unwrap();
+        assert!(game.data.turn == BorW::Black);
+        assert!(game.record.moves.len() == 2);
+        let updates = received(&mut rx)
+            .into_iter()
+            .filter_map(|x| match x {
+                ServerMessage::View { seq, .. } => Some((seq, true)),
+                ServerMessage::MoveApplied { seq, flipped, .. } => {
+                    assert!(flipped.len() == 1);
+                    Some((seq, false))
+                }
+                _ => None,
+            })
+            .collect::<Vec<(u64, bool)>>();
+        assert!(updates == [(0, true), (1, false), (2, false)]);
+        handle_message(a, &mut server_data, ClientMessage::Resync);
+        assert!(received(&mut rx)
+            .iter()
+            .any(|x| matches!(x, ServerMessage::View { seq: 2, .. })));
+    }
+    #[test]
+    fn time_control_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, mut rx_b) = connect(&mut server_data, 2);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions {
+                time_control: TimeControl::Fischer {
+                    main_ms: 60_000,
+                    increment_ms: 1_000,
+                },
+                ..Default::default()
+            }),
+        );
+        let clock = |messages: Vec<ServerMessage>| {
+            messages.into_iter().find_map(|x| match x {
+                ServerMessage::View { clock, .. } => clock,
+                ServerMessage::MoveApplied { clock, .. } => clock,
+                _ => None,
+            })
+        };
+        // The clock waits for the second player.
+        assert!(clock(received(&mut rx_a)).unwrap().running.is_none());
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        let state = clock(received(&mut rx_b)).unwrap();
+        assert!(state.running == Some(BorW::Black) && state.black_ms == 60_000);
+        let e6 = Position::from_notation("e6").unwrap();
+        handle_message(a, &mut server_data, ClientMessage::Put(e6));
+        let state = clock(received(&mut rx_b)).unwrap();
+        assert!(state.running == Some(BorW::White) && state.black_ms > 60_000);
+        check_flags(&mut server_data, Instant::now() + Duration::from_secs(59));
+        assert!(server_data.games[&GameID(1)].state == GameState::Playing);
+        check_flags(&mut server_data, Instant::now() + Duration::from_secs(61));
+        let over = received(&mut rx_a).into_iter().find_map(|x| match x {
+            ServerMessage::GameOver { winner, reason, .. } => Some((winner, reason)),
+            _ => None,
+        });
+        assert!(over == Some((Some(BorW::Black), EndReason::Time)));
+        let f4 = Position::from_notation("f4").unwrap();
+        handle_message(b, &mut server_data, ClientMessage::Put(f4));
+        assert!(error_code(&received(&mut rx_b)) == Some(ErrorCode::IllegalMove));
+    }
+    #[test]
+    fn lifecycle_test() {
+        let mut server_data = ServerData::new(2, Box::new(MemoryStore));
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, _rx_b) = connect(&mut server_data, 2);
+        let create = || ClientMessage::CreateGame(GameOptions::default());
+        handle_message(a, &mut server_data, create());
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        handle_message(a, &mut server_data, create());
+        let states = received(&mut rx_a)
+            .into_iter()
+            .filter_map(|x| match x {
+                ServerMessage::GameList(x) => Some(x),
+                _ => None,
+            })
+            .next_back()
+            .unwrap()
+            .iter()
+            .map(|x| x.state)
+            .collect::<Vec<GameState>>();
+        assert!(states == [GameState::Playing, GameState::Waiting]);
+        disconnect(b, &mut server_data);
+        let later = Instant::now() + server_data.game_idle_timeout;
+        sweep_games(&mut server_data, later);
+        assert!(server_data.games.len() == 1);
+        assert!(server_data.archive[0].state == GameState::Abandoned);
+        disconnect(a, &mut server_data);
+        let later = Instant::now() + server_data.game_idle_timeout;
+        sweep_games(&mut server_data, later);
+        assert!(server_data.games.is_empty() && server_data.archive.len() == 1);
+        let (c, mut rx_c) = connect(&mut server_data, 3);
+        handle_message(c, &mut server_data, create());
+        assert!(received(&mut rx_c)
+            .iter()
+            .any(|x| matches!(x, ServerMessage::GameList(x) if x[0].id == GameID(3))));
+    }
+    #[test]
+    fn negotiation_test() {
+        let mut server_data = ServerData::new(2, Box::new(MemoryStore));
+        let (a, mut rx_a) = connect(&mut server_data, 1);
+        let (b, mut rx_b) = connect(&mut server_data, 2);
+        let (c, mut rx_c) = connect(&mut server_data, 3);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        for x in [b, c] {
+            handle_message(x, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        }
+        let e6 = Position::from_notation("e6").unwrap();
+        handle_message(a, &mut server_data, ClientMessage::Put(e6));
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::Offer(Proposal::Takeback),
+        );
+        assert!(received(&mut rx_b).iter().any(|x| matches!(
+            x,
+            ServerMessage::Offered {
+                proposal: Proposal::Takeback,
+                by: BorW::Black
+            }
+        )));
+        handle_message(
+            b,
+            &mut server_data,
+            ClientMessage::Accept(Proposal::Takeback),
+        );
+        assert!(server_data.games[&GameID(1)].record.moves.is_empty());
+        handle_message(b, &mut server_data, ClientMessage::Decline(Proposal::Draw));
+        assert!(error_code(&received(&mut rx_b)) == Some(ErrorCode::NoOffer));
+        handle_message(c, &mut server_data, ClientMessage::Offer(Proposal::Draw));
+        assert!(error_code(&received(&mut rx_c)) == Some(ErrorCode::NotSeated));
+        handle_message(c, &mut server_data, ClientMessage::Reset);
+        assert!(error_code(&received(&mut rx_c)) == Some(ErrorCode::NotSeated));
+        handle_message(a, &mut server_data, ClientMessage::Offer(Proposal::Draw));
+        handle_message(b, &mut server_data, ClientMessage::Offer(Proposal::Draw));
+        let over = |messages: Vec<ServerMessage>| {
+            messages.into_iter().find_map(|x| match x {
+                ServerMessage::GameOver { winner, reason, .. } => Some((winner, reason)),
+                _ => None,
+            })
+        };
+        assert!(over(received(&mut rx_a)) == Some((None, EndReason::Agreement)));
+        handle_message(a, &mut server_data, ClientMessage::Offer(Proposal::Rematch));
+        handle_message(
+            b,
+            &mut server_data,
+            ClientMessage::Accept(Proposal::Rematch),
+        );
+        let rematch = &server_data.games[&GameID(2)];
+        assert!(rematch.black == Some(PlayerId::Guest(b)));
+        assert!(rematch.white == Some(PlayerId::Guest(a)));
+        handle_message(b, &mut server_data, ClientMessage::Resign);
+        assert!(over(received(&mut rx_a)) == Some((Some(BorW::White), EndReason::Resignation)));
+        handle_message(a, &mut server_data, ClientMessage::Reset);
+        assert!(server_data.games[&GameID(2)].state == GameState::Finished);
+        handle_message(b, &mut server_data, ClientMessage::Reset);
+        assert!(server_data.games[&GameID(2)].state == GameState::Playing);
+    }
+    #[test]
+    fn seat_test() {
+        let a = PlayerId::Guest("127.0.0.1:1".parse().unwrap());
+        let b = PlayerId::Guest("127.0.0.1:2".parse().unwrap());
+        let c = PlayerId::Guest("127.0.0.1:3".parse().unwrap());
+        let mut game = Game::new();
+        assert!(game.join(a) == Role::Player(BorW::Black));
+        assert!(game.join(a) == Role::Player(BorW::Black));
+        assert!(game.join(b) == Role::Player(BorW::White));
+        assert!(game.join(c) == Role::Spectator);
+        game.leave(a);
+        assert!(game.color_of(a).is_none());
+        assert!(game.join(c) == Role::Player(BorW::Black));
+    }
+    #[test]
+    fn metrics_test() {
+        let mut server_data = ServerData::new(1, Box::new(MemoryStore));
+        let (a, _rx_a) = connect(&mut server_data, 1);
+        let (b, _rx_b) = connect(&mut server_data, 2);
+        handle_message(
+            a,
+            &mut server_data,
+            ClientMessage::CreateGame(GameOptions::default()),
+        );
+        handle_message(b, &mut server_data, ClientMessage::SelectGame(GameID(1)));
+        let e6 = Position::from_notation("e6").unwrap();
+        handle_message(a, &mut server_data, ClientMessage::Put(e6));
+        handle_message(a, &mut server_data, ClientMessage::Put(e6));
+        let text = metrics::render(&server_data);
+        for line in [
+            "reversi_connections 2",
+            "reversi_games{state=\"playing\"} 1",
+            "reversi_messages_total{type=\"hello\"} 2",
+            "reversi_messages_total{type=\"put\"} 2",
+            "reversi_errors_total{code=\"NotYourTurn\"} 1",
+            "reversi_move_latency_seconds_bucket{player=\"human\",le=\"+Inf\"} 1",
+            "reversi_move_latency_seconds_count{player=\"human\"} 1",
+        ] {
+            assert!(text.lines().any(|x| x == line), "missing {}", line);
+        }
+        assert!(!text.contains("player=\"computer\""));
     }
-    Ok(())
 }
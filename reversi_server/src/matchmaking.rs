@@ -0,0 +1,139 @@
+use super::*;
+
+pub struct QueuedPlayer {
+    pub addr: SocketAddr,
+    pub rating: f64,
+    pub since: Instant,
+}
+
+/// Largest rating gap accepted for a pairing. It starts narrow and widens
+/// the longer either player has been waiting.
+fn rating_window(waited: Duration) -> f64 {
+    100.0 + 20.0 * waited.as_secs_f64()
+}
+
+pub fn handle_findmatch(addr: SocketAddr, server_data: &mut ServerData) -> Result<(), ClientError> {
+    if !server_data.queue.iter().any(|x| x.addr == addr) {
+        let rating = match server_data.connections.get(&addr).unwrap().token {
+            Some(x) => rating(server_data, x),
+            None => DEFAULT_RATING,
+        };
+        server_data.queue.push(QueuedPlayer {
+            addr,
+            rating,
+            since: Instant::now(),
+        });
+    }
+    send_message(
+        &server_data.connections.get(&addr).unwrap().sender,
+        &ServerMessage::Searching,
+    );
+    match_players(server_data, Instant::now());
+    Ok(())
+}
+
+pub fn handle_cancelmatch(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+) -> Result<(), ClientError> {
+    leave_queue(addr, server_data);
+    send_message(
+        &server_data.connections.get(&addr).unwrap().sender,
+        &ServerMessage::SearchCancelled,
+    );
+    Ok(())
+}
+
+pub fn leave_queue(addr: SocketAddr, server_data: &mut ServerData) {
+    server_data.queue.retain(|x| x.addr != addr);
+}
+
+/// Pairs the closest ratings first, then gives everyone who has waited
+/// longer than `match_timeout` a computer opponent.
+pub fn match_players(server_data: &mut ServerData, now: Instant) {
+    loop {
+        let queue = &server_data.queue;
+        let mut best: Option<(usize, usize, f64)> = None;
+        for i in 0..queue.len() {
+            for j in i + 1..queue.len() {
+                let gap = (queue[i].rating - queue[j].rating).abs();
+                let waited = now.duration_since(queue[i].since.min(queue[j].since));
+                if gap <= rating_window(waited) && best.is_none_or(|x| gap < x.2) {
+                    best = Some((i, j, gap));
+                }
+            }
+        }
+        let (i, j) = match best {
+            Some((i, j, _)) => (i, j),
+            None => break,
+        };
+        let second = server_data.queue.remove(j);
+        let first = server_data.queue.remove(i);
+        start_match(server_data, first.addr, Some(second.addr));
+    }
+    let timeout = server_data.match_timeout;
+    let (expired, waiting) = server_data
+        .queue
+        .drain(..)
+        .partition::<Vec<QueuedPlayer>, _>(|x| now.duration_since(x.since) >= timeout);
+    server_data.queue = waiting;
+    for x in expired {
+        start_match(server_data, x.addr, None);
+    }
+}
+
+/// Creates a game for two queued players, or one player and the computer
+/// when `second` is `None`, with colours drawn at random.
+fn start_match(server_data: &mut ServerData, first: SocketAddr, second: Option<SocketAddr>) {
+    let players = [Some(first), second];
+    let game_id = match add_new_game(server_data) {
+        Ok(x) => x,
+        Err(_) => {
+            for addr in players.into_iter().flatten() {
+                send_error(
+                    addr,
+                    server_data,
+                    ClientError::new(ErrorCode::TooManyGames, "no more games can be created"),
+                );
+            }
+            return;
+        }
+    };
+    let ids = players.map(|x| match x {
+        Some(addr) => server_data.connections.get(&addr).unwrap().player_id(addr),
+        None => PlayerId::Computer,
+    });
+    let names = players.map(|x| match x {
+        Some(addr) => server_data.connections.get(&addr).unwrap().nickname.clone(),
+        None => COMPUTER_NAME.to_string(),
+    });
+    let black = if rand::random() { 0 } else { 1 };
+    let game = server_data.games.get_mut(&game_id).unwrap();
+    game.black = Some(ids[black]);
+    game.white = Some(ids[1 - black]);
+    info!(
+        game_id = game_id.0,
+        black = %names[black],
+        white = %names[1 - black],
+        "matched"
+    );
+    for (i, addr) in players.into_iter().enumerate() {
+        let addr = match addr {
+            Some(x) => x,
+            None => continue,
+        };
+        send_message(
+            &server_data.connections.get(&addr).unwrap().sender,
+            &ServerMessage::MatchFound {
+                game_id,
+                color: if i == black { BorW::Black } else { BorW::White },
+                opponent: names[1 - i].clone(),
+            },
+        );
+        // The game was just created, so joining cannot fail.
+        let _ = join_game(addr, server_data, game_id);
+    }
+    play_computer(server_data, game_id);
+    server_data.save_game(game_id);
+    broadcast_game_list(server_data);
+}
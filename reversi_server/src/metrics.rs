@@ -0,0 +1,158 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Upper bounds of the move latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 8] = [0.001, 0.005, 0.025, 0.1, 0.5, 1.0, 2.5, 10.0];
+
+/// Who made a move, as a metric label.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mover {
+    /// From receiving `Put` until the update is sent.
+    Human,
+    /// From asking the worker until the update is sent.
+    Computer,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of `LATENCY_BUCKETS`, not cumulative; the
+    /// last entry is for anything slower.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+/// Counters since the server started.
+#[derive(Default)]
+pub struct Metrics {
+    /// Client messages handled, by type.
+    messages: BTreeMap<&'static str, u64>,
+    /// Errors sent back to clients, by code.
+    errors: BTreeMap<String, u64>,
+    /// Connections that ended with an I/O, TLS or WebSocket error.
+    connection_errors: u64,
+    move_latency: BTreeMap<Mover, Histogram>,
+}
+
+impl Metrics {
+    pub fn count_message(&mut self, message: &ClientMessage) {
+        *self.messages.entry(message.kind()).or_default() += 1;
+    }
+    pub fn count_error(&mut self, code: ErrorCode) {
+        *self.errors.entry(format!("{:?}", code)).or_default() += 1;
+    }
+    pub fn count_connection_error(&mut self) {
+        self.connection_errors += 1;
+    }
+    pub fn observe_move(&mut self, mover: Mover, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let histogram = self.move_latency.entry(mover).or_default();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|x| seconds <= *x)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+/// The metrics in the Prometheus text format.
+pub fn render(server_data: &ServerData) -> String {
+    let metrics = &server_data.metrics;
+    let mut out = String::new();
+    header(
+        &mut out,
+        "reversi_connections",
+        "gauge",
+        "Open WebSocket connections.",
+    );
+    let _ = writeln!(out, "reversi_connections {}", server_data.connections.len());
+    header(
+        &mut out,
+        "reversi_games",
+        "gauge",
+        "Games in memory by state.",
+    );
+    for state in [
+        GameState::Waiting,
+        GameState::Playing,
+        GameState::Finished,
+        GameState::Abandoned,
+    ] {
+        let count = server_data
+            .games
+            .values()
+            .filter(|x| x.state == state)
+            .count();
+        let _ = writeln!(
+            out,
+            "reversi_games{{state=\"{}\"}} {}",
+            format!("{:?}", state).to_lowercase(),
+            count
+        );
+    }
+    header(
+        &mut out,
+        "reversi_messages_total",
+        "counter",
+        "Client messages handled by type.",
+    );
+    for (kind, count) in &metrics.messages {
+        let _ = writeln!(out, "reversi_messages_total{{type=\"{}\"}} {}", kind, count);
+    }
+    header(
+        &mut out,
+        "reversi_errors_total",
+        "counter",
+        "Errors sent to clients by code.",
+    );
+    for (code, count) in &metrics.errors {
+        let _ = writeln!(out, "reversi_errors_total{{code=\"{}\"}} {}", code, count);
+    }
+    header(
+        &mut out,
+        "reversi_connection_errors_total",
+        "counter",
+        "Connections that ended with an error.",
+    );
+    let _ = writeln!(
+        out,
+        "reversi_connection_errors_total {}",
+        metrics.connection_errors
+    );
+    header(
+        &mut out,
+        "reversi_move_latency_seconds",
+        "histogram",
+        "Time from a move being asked for until it is sent to the players.",
+    );
+    for (mover, histogram) in &metrics.move_latency {
+        let player = match mover {
+            Mover::Human => "human",
+            Mover::Computer => "computer",
+        };
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "reversi_move_latency_seconds_bucket{{player=\"{}\",le=\"{}\"}} {}",
+                player, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "reversi_move_latency_seconds_bucket{{player=\"{}\",le=\"+Inf\"}} {}\n\
+             reversi_move_latency_seconds_sum{{player=\"{}\"}} {}\n\
+             reversi_move_latency_seconds_count{{player=\"{}\"}} {}",
+            player, histogram.count, player, histogram.sum, player, histogram.count
+        );
+    }
+    out
+}
@@ -0,0 +1,246 @@
+use super::*;
+use ratings::end_game;
+
+/// The current game of `addr` and the colour they play in it.
+fn seat(addr: SocketAddr, server_data: &ServerData) -> Result<(GameID, BorW), ClientError> {
+    let game_id = current_game(addr, server_data)?;
+    let player = server_data.connections.get(&addr).unwrap().player_id(addr);
+    let game = server_data
+        .games
+        .get(&game_id)
+        .ok_or_else(|| unknown_game(game_id))?;
+    let color = game
+        .color_of(player)
+        .ok_or_else(|| ClientError::new(ErrorCode::NotSeated, "only the players can do this"))?;
+    Ok((game_id, color))
+}
+
+fn not_allowed(message: &str) -> ClientError {
+    ClientError::new(ErrorCode::NotAllowed, message)
+}
+
+pub fn handle_resign(addr: SocketAddr, server_data: &mut ServerData) -> Result<(), ClientError> {
+    let (game_id, color) = seat(addr, server_data)?;
+    if server_data.games.get(&game_id).unwrap().state != GameState::Playing {
+        return Err(not_allowed("the game is not being played"));
+    }
+    end_game(
+        server_data,
+        game_id,
+        Some(get_another_color(color)),
+        EndReason::Resignation,
+    );
+    Ok(())
+}
+
+pub fn handle_offer(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    proposal: Proposal,
+) -> Result<(), ClientError> {
+    let (game_id, color) = seat(addr, server_data)?;
+    let game = server_data.games.get_mut(&game_id).unwrap();
+    let refusal = match proposal {
+        Proposal::Draw if game.state != GameState::Playing => Some("the game is not being played"),
+        Proposal::Takeback if game.is_over() || last_put_by(&game.record, color).is_none() => {
+            Some("there is no move to take back")
+        }
+        Proposal::Rematch if !game.is_over() => Some("the game is not over"),
+        _ => None,
+    };
+    if let Some(x) = refusal {
+        return Err(not_allowed(x));
+    }
+    let opponent = get_another_color(color);
+    if game.offer == Some((proposal, opponent)) {
+        return agree(server_data, game_id, proposal, opponent);
+    }
+    let opponent_seat = match opponent {
+        BorW::Black => game.black,
+        BorW::White => game.white,
+    };
+    match opponent_seat {
+        // Nobody else has a say.
+        None if proposal == Proposal::Reset => agree(server_data, game_id, proposal, color),
+        None => Err(not_allowed("there is no opponent")),
+        // The computer never takes a draw but agrees to everything else.
+        Some(PlayerId::Computer) if proposal == Proposal::Draw => {
+            broadcast_to_game(
+                server_data,
+                game_id,
+                &ServerMessage::Declined {
+                    proposal,
+                    by: opponent,
+                },
+            );
+            Ok(())
+        }
+        Some(PlayerId::Computer) => agree(server_data, game_id, proposal, color),
+        Some(_) => {
+            game.offer = Some((proposal, color));
+            broadcast_to_game(
+                server_data,
+                game_id,
+                &ServerMessage::Offered {
+                    proposal,
+                    by: color,
+                },
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Checks that the opponent of `addr` has offered `proposal` and withdraws
+/// the offer.
+fn take_offer(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    proposal: Proposal,
+) -> Result<(GameID, BorW), ClientError> {
+    let (game_id, color) = seat(addr, server_data)?;
+    let game = server_data.games.get_mut(&game_id).unwrap();
+    let opponent = get_another_color(color);
+    if game.offer != Some((proposal, opponent)) {
+        return Err(ClientError::new(
+            ErrorCode::NoOffer,
+            "your opponent has not offered this",
+        ));
+    }
+    game.offer = None;
+    Ok((game_id, opponent))
+}
+
+pub fn handle_accept(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    proposal: Proposal,
+) -> Result<(), ClientError> {
+    let (game_id, proposer) = take_offer(addr, server_data, proposal)?;
+    agree(server_data, game_id, proposal, proposer)
+}
+
+pub fn handle_decline(
+    addr: SocketAddr,
+    server_data: &mut ServerData,
+    proposal: Proposal,
+) -> Result<(), ClientError> {
+    let (game_id, proposer) = take_offer(addr, server_data, proposal)?;
+    broadcast_to_game(
+        server_data,
+        game_id,
+        &ServerMessage::Declined {
+            proposal,
+            by: get_another_color(proposer),
+        },
+    );
+    Ok(())
+}
+
+/// Index of the last put of `color` in `record`.
+fn last_put_by(record: &GameRecord, color: BorW) -> Option<usize> {
+    let color_at = |i: usize| {
+        if i.is_multiple_of(2) {
+            record.first
+        } else {
+            get_another_color(record.first)
+        }
+    };
+    (0..record.moves.len())
+        .rev()
+        .find(|&i| matches!(record.moves[i], Move::Put(_)) && color_at(i) == color)
+}
+
+/// Carries out a proposal both players agreed on.
+fn agree(
+    server_data: &mut ServerData,
+    game_id: GameID,
+    proposal: Proposal,
+    proposer: BorW,
+) -> Result<(), ClientError> {
+    server_data.games.get_mut(&game_id).unwrap().offer = None;
+    match proposal {
+        Proposal::Draw => end_game(server_data, game_id, None, EndReason::Agreement),
+        Proposal::Takeback => take_back(server_data, game_id, proposer),
+        Proposal::Rematch => return rematch(server_data, game_id),
+        Proposal::Reset => reset_game(server_data, game_id),
+    }
+    Ok(())
+}
+
+fn take_back(server_data: &mut ServerData, game_id: GameID, color: BorW) {
+    let game = server_data.games.get_mut(&game_id).unwrap();
+    let index = match last_put_by(&game.record, color) {
+        Some(x) => x,
+        None => return,
+    };
+    let mut record = game.record.clone();
+    record.moves.truncate(index);
+    // The moves were legal when they were played, so replaying them is too.
+    let rebuilt = Game::from_record(record).unwrap();
+    game.data = rebuilt.data;
+    game.record = rebuilt.record;
+    let turn = game.data.turn;
+    if let Some(clock) = game.clock.as_mut().filter(|x| x.is_running()) {
+        clock.punch(Some(turn), Instant::now());
+    }
+    server_data.save_game(game_id);
+    broadcast_view(server_data, game_id);
+    play_computer(server_data, game_id);
+}
+
+fn reset_game(server_data: &mut ServerData, game_id: GameID) {
+    let game = server_data.games.get_mut(&game_id).unwrap();
+    game.data = ReversiData::new();
+    game.record = GameRecord::new();
+    game.state = GameState::Waiting;
+    game.clock = Clock::new(game.time_control);
+    game.start(Instant::now());
+    play_computer(server_data, game_id);
+    server_data.save_game(game_id);
+    broadcast_view(server_data, game_id);
+}
+
+/// The connection a seat holder is on, if they are connected.
+fn addr_of(server_data: &ServerData, player: PlayerId) -> Option<SocketAddr> {
+    match player {
+        PlayerId::Guest(addr) => Some(addr).filter(|x| server_data.connections.contains_key(x)),
+        PlayerId::Session(token) => server_data
+            .connections
+            .iter()
+            .find(|x| x.1.token == Some(token))
+            .map(|x| *x.0),
+        PlayerId::Computer => None,
+    }
+}
+
+/// Seats both players in a new game with the same options and colours
+/// swapped.
+fn rematch(server_data: &mut ServerData, game_id: GameID) -> Result<(), ClientError> {
+    let old = server_data.games.get(&game_id).unwrap();
+    let (black, white) = (old.white, old.black);
+    let (time_control, level) = (old.time_control, old.level);
+    let new_game_id = add_new_game(server_data)
+        .map_err(|_| ClientError::new(ErrorCode::TooManyGames, "no more games can be created"))?;
+    let game = server_data.games.get_mut(&new_game_id).unwrap();
+    game.set_time_control(time_control);
+    game.level = level;
+    game.black = black;
+    game.white = white;
+    info!(game_id = new_game_id.0, rematch_of = game_id.0, "rematch");
+    for player in [black, white].into_iter().flatten() {
+        if let PlayerId::Session(token) = player {
+            if let Some(away) = server_data.away.get_mut(&token) {
+                away.0 = Some(new_game_id);
+            }
+        }
+        if let Some(addr) = addr_of(server_data, player) {
+            // The game was just created, so joining cannot fail.
+            let _ = join_game(addr, server_data, new_game_id);
+        }
+    }
+    play_computer(server_data, new_game_id);
+    server_data.save_game(new_game_id);
+    broadcast_game_list(server_data);
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use super::*;
+use storage::{FinishedGame, RatedPlayer};
+
+/// Players listed in a `Leaderboard` reply.
+const LEADERBOARD_SIZE: usize = 10;
+
+/// Rating assumed for players who have not finished a rated game.
+pub const DEFAULT_RATING: f64 = 1500.0;
+
+/// Largest rating change a single game can cause.
+const K_FACTOR: f64 = 32.0;
+
+/// New Elo ratings of Black and White after a game.
+pub fn elo_update(black: f64, white: f64, winner: Option<BorW>) -> (f64, f64) {
+    let expected = 1.0 / (1.0 + 10f64.powf((white - black) / 400.0));
+    let score = match winner {
+        Some(BorW::Black) => 1.0,
+        Some(BorW::White) => 0.0,
+        None => 0.5,
+    };
+    let delta = K_FACTOR * (score - expected);
+    (black + delta, white - delta)
+}
+
+/// Ratings belong to a session rather than a nickname. A rated session never
+/// expires, so its nickname stays reserved and nobody else can log in under
+/// it to take over the rating.
+pub fn rating(server_data: &ServerData, token: SessionToken) -> f64 {
+    server_data
+        .ratings
+        .get(&token)
+        .copied()
+        .unwrap_or(DEFAULT_RATING)
+}
+
+/// Writes every rating with the current nickname of its session.
+pub fn save_ratings(server_data: &ServerData) {
+    let ratings = server_data
+        .ratings
+        .iter()
+        .filter_map(|(k, v)| {
+            let nickname = server_data.sessions.get(k)?.clone();
+            Some((
+                *k,
+                RatedPlayer {
+                    nickname,
+                    rating: *v,
+                },
+            ))
+        })
+        .collect();
+    if let Err(e) = server_data.store.save_ratings(&ratings) {
+        warn!(error = %e, "cannot save ratings");
+    }
+}
+
+/// Ends the game if neither side can move any more.
+pub fn finish_game(server_data: &mut ServerData, game_id: GameID) {
+    let winner = match server_data.games.get(&game_id) {
+        Some(x) => match x.data.field.get_gameresult() {
+            GameResult::Playing => return,
+            GameResult::Win(x) => Some(x),
+            GameResult::Draw => None,
+        },
+        None => return,
+    };
+    end_game(server_data, game_id, winner, EndReason::Board);
+}
+
+/// Records a game that has just ended and, when both seats belong to logged
+/// in players, updates their ratings. Everyone in the game is told the
+/// result.
+pub fn end_game(
+    server_data: &mut ServerData,
+    game_id: GameID,
+    winner: Option<BorW>,
+    reason: EndReason,
+) {
+    let game = match server_data.games.get_mut(&game_id) {
+        Some(x) if !x.is_over() => x,
+        _ => return,
+    };
+    game.state = GameState::Finished;
+    game.offer = None;
+    if let Some(clock) = &mut game.clock {
+        clock.punch(None, Instant::now());
+    }
+    let seats = [game.black, game.white];
+    let field = game.data.field.clone();
+    let record = game.record.clone();
+    let names = seats.map(|x| x.and_then(|x| player_name(server_data, x)));
+    let finished = FinishedGame {
+        id: game_id,
+        black: names[0].clone().unwrap_or_default(),
+        white: names[1].clone().unwrap_or_default(),
+        winner,
+        reason,
+        black_discs: field.count(BorW::Black),
+        white_discs: field.count(BorW::White),
+        record,
+        finished_at: now_millis(),
+    };
+    if let Err(e) = server_data.store.append_result(&finished) {
+        warn!(game_id = game_id.0, error = %e, "cannot record the result");
+    }
+    let mut rating_changes = Vec::new();
+    if let (
+        [Some(PlayerId::Session(black_token)), Some(PlayerId::Session(white_token))],
+        [Some(black), Some(white)],
+    ) = (seats, names)
+    {
+        let old = (
+            rating(server_data, black_token),
+            rating(server_data, white_token),
+        );
+        let new = elo_update(old.0, old.1, winner);
+        server_data.ratings.insert(black_token, new.0);
+        server_data.ratings.insert(white_token, new.1);
+        save_ratings(server_data);
+        rating_changes = vec![
+            RatingChange {
+                nickname: black,
+                old: old.0.round() as i32,
+                new: new.0.round() as i32,
+            },
+            RatingChange {
+                nickname: white,
+                old: old.1.round() as i32,
+                new: new.1.round() as i32,
+            },
+        ];
+    }
+    info!(game_id = game_id.0, ?winner, ?reason, "finished");
+    let message = ServerMessage::GameOver {
+        winner,
+        reason,
+        rating_changes,
+    };
+    server_data
+        .connections
+        .values()
+        .filter(|x| x.game_id == Some(game_id))
+        .for_each(|x| send_message(&x.sender, &message));
+}
+
+pub fn handle_leaderboard(addr: SocketAddr, server_data: &ServerData) -> Result<(), ClientError> {
+    let mut ranking = server_data
+        .ratings
+        .iter()
+        .filter_map(|(k, v)| Some((*k, server_data.sessions.get(k)?.clone(), *v)))
+        .collect::<Vec<(SessionToken, String, f64)>>();
+    ranking.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.1.cmp(&b.1)));
+    let entry =
+        |(i, (_, nickname, rating)): (usize, &(SessionToken, String, f64))| LeaderboardEntry {
+            rank: i as u32 + 1,
+            nickname: nickname.clone(),
+            rating: rating.round() as i32,
+        };
+    let connection = server_data.connections.get(&addr).unwrap();
+    let you = match connection.token {
+        Some(token) => ranking
+            .iter()
+            .enumerate()
+            .find(|(_, x)| x.0 == token)
+            .map(entry),
+        None => None,
+    };
+    send_message(
+        &connection.sender,
+        &ServerMessage::Leaderboard {
+            top: ranking
+                .iter()
+                .take(LEADERBOARD_SIZE)
+                .enumerate()
+                .map(entry)
+                .collect(),
+            you,
+        },
+    );
+    Ok(())
+}
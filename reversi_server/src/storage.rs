@@ -0,0 +1,265 @@
+use reversi_core::{BorW, GameRecord};
+use reversi_message::{EndReason, GameID, GameState, Players, SessionToken, TimeControl};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// What survives a restart of one game.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavedGame {
+    pub id: GameID,
+    pub record: GameRecord,
+    /// Clocks restart with full time after a restart.
+    #[serde(default)]
+    pub time_control: TimeControl,
+    #[serde(default)]
+    pub state: GameState,
+    /// Who was in the game when it was last saved.
+    #[serde(default)]
+    pub players: Players,
+    /// Seats that can be taken back after a restart. Guests cannot come
+    /// back, so their seats are saved as open.
+    #[serde(default)]
+    pub black: Option<SavedSeat>,
+    #[serde(default)]
+    pub white: Option<SavedSeat>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum SavedSeat {
+    /// Resuming the session after the restart takes the seat back.
+    Session {
+        token: SessionToken,
+        nickname: String,
+    },
+    Computer {
+        level: u8,
+    },
+}
+
+/// A rated player, kept under their session token. The token is their
+/// account, so it still holds the nickname after a restart.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct RatedPlayer {
+    pub nickname: String,
+    pub rating: f64,
+}
+
+/// A finished game as kept in the results log.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FinishedGame {
+    pub id: GameID,
+    pub black: String,
+    pub white: String,
+    /// `None` for a draw.
+    pub winner: Option<BorW>,
+    pub reason: EndReason,
+    pub black_discs: usize,
+    pub white_discs: usize,
+    pub record: GameRecord,
+    /// Milliseconds since the Unix epoch.
+    pub finished_at: u64,
+}
+
+pub trait GameStore: Send {
+    fn load_games(&self) -> std::io::Result<Vec<SavedGame>>;
+    fn save_game(&self, game: &SavedGame) -> std::io::Result<()>;
+    fn remove_game(&self, id: GameID) -> std::io::Result<()>;
+    /// Moves a game that is no longer active out of the games loaded at
+    /// startup.
+    fn archive_game(&self, game: &SavedGame) -> std::io::Result<()>;
+    fn load_archive(&self) -> std::io::Result<Vec<SavedGame>>;
+    fn append_result(&self, game: &FinishedGame) -> std::io::Result<()>;
+    fn load_ratings(&self) -> std::io::Result<HashMap<SessionToken, RatedPlayer>>;
+    fn save_ratings(&self, ratings: &HashMap<SessionToken, RatedPlayer>) -> std::io::Result<()>;
+}
+
+/// Keeps nothing; used when no storage directory is configured.
+pub struct MemoryStore;
+
+impl GameStore for MemoryStore {
+    fn load_games(&self) -> std::io::Result<Vec<SavedGame>> {
+        Ok(Vec::new())
+    }
+    fn save_game(&self, _: &SavedGame) -> std::io::Result<()> {
+        Ok(())
+    }
+    fn remove_game(&self, _: GameID) -> std::io::Result<()> {
+        Ok(())
+    }
+    fn archive_game(&self, _: &SavedGame) -> std::io::Result<()> {
+        Ok(())
+    }
+    fn load_archive(&self) -> std::io::Result<Vec<SavedGame>> {
+        Ok(Vec::new())
+    }
+    fn append_result(&self, _: &FinishedGame) -> std::io::Result<()> {
+        Ok(())
+    }
+    fn load_ratings(&self) -> std::io::Result<HashMap<SessionToken, RatedPlayer>> {
+        Ok(HashMap::new())
+    }
+    fn save_ratings(&self, _: &HashMap<SessionToken, RatedPlayer>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// One JSON file per game under `<dir>/games`, or `<dir>/archive` once the
+/// game is over or abandoned, ratings in
+/// `<dir>/ratings.json` and finished games appended to `<dir>/results.jsonl`.
+/// Files are written to a temporary name and renamed, so a crash never
+/// leaves half a game behind.
+pub struct FileStore {
+    dir: PathBuf,
+    games_dir: PathBuf,
+    archive_dir: PathBuf,
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(tmp, path)
+}
+
+fn invalid_data(path: &Path, e: serde_json::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+fn load_dir(dir: &Path) -> std::io::Result<Vec<SavedGame>> {
+    let mut games = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        let game = serde_json::from_slice::<SavedGame>(&fs::read(&path)?)
+            .map_err(|e| invalid_data(&path, e))?;
+        games.push(game);
+    }
+    games.sort_by_key(|x| x.id);
+    Ok(games)
+}
+
+impl FileStore {
+    pub fn open(dir: PathBuf) -> std::io::Result<Self> {
+        let games_dir = dir.join("games");
+        let archive_dir = dir.join("archive");
+        fs::create_dir_all(&games_dir)?;
+        fs::create_dir_all(&archive_dir)?;
+        Ok(FileStore {
+            dir,
+            games_dir,
+            archive_dir,
+        })
+    }
+    fn game_path(&self, id: GameID) -> PathBuf {
+        self.games_dir.join(format!("{}.json", id.0))
+    }
+}
+
+impl GameStore for FileStore {
+    fn load_games(&self) -> std::io::Result<Vec<SavedGame>> {
+        load_dir(&self.games_dir)
+    }
+    fn save_game(&self, game: &SavedGame) -> std::io::Result<()> {
+        write_atomic(&self.game_path(game.id), &serde_json::to_vec(game)?)
+    }
+    fn remove_game(&self, id: GameID) -> std::io::Result<()> {
+        match fs::remove_file(self.game_path(id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+    fn archive_game(&self, game: &SavedGame) -> std::io::Result<()> {
+        write_atomic(
+            &self.archive_dir.join(format!("{}.json", game.id.0)),
+            &serde_json::to_vec(game)?,
+        )?;
+        self.remove_game(game.id)
+    }
+    fn load_archive(&self) -> std::io::Result<Vec<SavedGame>> {
+        load_dir(&self.archive_dir)
+    }
+    fn append_result(&self, game: &FinishedGame) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(game)?;
+        line.push(b'\n');
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("results.jsonl"))?
+            .write_all(&line)
+    }
+    fn load_ratings(&self) -> std::io::Result<HashMap<SessionToken, RatedPlayer>> {
+        let path = self.dir.join("ratings.json");
+        match fs::read(&path) {
+            Ok(x) => serde_json::from_slice(&x).map_err(|e| invalid_data(&path, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+    fn save_ratings(&self, ratings: &HashMap<SessionToken, RatedPlayer>) -> std::io::Result<()> {
+        write_atomic(
+            &self.dir.join("ratings.json"),
+            &serde_json::to_vec(ratings)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reversi_core::Position;
+    #[test]
+    fn file_store_test() {
+        let dir = std::env::temp_dir().join(format!("reversi_store_{}", std::process::id()));
+        let store = FileStore::open(dir.clone()).unwrap();
+        let mut record = GameRecord::new();
+        record.play(Position::new(4, 2).unwrap()).unwrap();
+        let game = SavedGame {
+            id: GameID(3),
+            record,
+            time_control: TimeControl::SuddenDeath { main_ms: 60_000 },
+            state: GameState::Playing,
+            players: Players::default(),
+            black: Some(SavedSeat::Computer { level: 3 }),
+            white: None,
+        };
+        store.save_game(&game).unwrap();
+        store
+            .save_game(&SavedGame {
+                id: GameID(1),
+                record: GameRecord::new(),
+                time_control: TimeControl::Unlimited,
+                state: GameState::Waiting,
+                players: Players::default(),
+                black: None,
+                white: None,
+            })
+            .unwrap();
+        let loaded = FileStore::open(dir.clone()).unwrap().load_games().unwrap();
+        assert!(loaded.len() == 2);
+        assert!(loaded[1].id == GameID(3));
+        assert!(loaded[1].record == game.record);
+        assert!(loaded[1].time_control == game.time_control);
+        assert!(loaded[1].black == game.black);
+        store.archive_game(&game).unwrap();
+        assert!(store.load_games().unwrap().len() == 1);
+        assert!(store.load_archive().unwrap()[0].id == GameID(3));
+        assert!(store.load_ratings().unwrap().is_empty());
+        let alice = RatedPlayer {
+            nickname: "alice".to_string(),
+            rating: 1516.0,
+        };
+        let ratings = HashMap::from([(SessionToken(uuid::Uuid::new_v4()), alice)]);
+        store.save_ratings(&ratings).unwrap();
+        assert!(store.load_ratings().unwrap() == ratings);
+        fs::write(dir.join("games/2.json"), "{").unwrap();
+        assert!(store.load_games().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use std::{io, path::Path, sync::Arc};
+use tokio_rustls::{rustls, TlsAcceptor};
+
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), e),
+    )
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Terminates TLS with the PEM certificate chain in `cert`, leaf first, and
+/// the private key in `key`.
+pub fn acceptor(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .map_err(|e| invalid(cert, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(cert, e))?;
+    if certs.is_empty() {
+        return Err(invalid(cert, "no certificate found"));
+    }
+    let private_key = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, e))?;
+    let config = rustls::ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid(cert, e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+        .map_err(|e| invalid(key, e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{respond, sniff, Request};
+    use crate::storage::MemoryStore;
+    use crate::ServerData;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    #[tokio::test]
+    async fn tls_test() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("reversi_tls_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert, generated.cert.pem()).unwrap();
+        assert!(acceptor(&cert, &key).is_err());
+        std::fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+        let tls = acceptor(&cert, &key).unwrap();
+        assert!(acceptor(&key, &key).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let server_task = tokio::spawn(async move {
+            let server_data = Mutex::new(ServerData::new(1, Box::new(MemoryStore)));
+            let stream = tls.accept(server).await.unwrap();
+            match sniff(stream).await.unwrap() {
+                Request::Http {
+                    stream,
+                    method,
+                    path,
+                } => {
+                    respond(stream, &server_data, &method, &path).await.unwrap();
+                }
+                Request::WebSocket(_) => panic!("a plain GET has to be answered over HTTP"),
+            }
+        });
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(generated.cert.der().clone()).unwrap();
+        let config = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(config));
+        let name = "localhost".try_into().unwrap();
+        let mut stream = connector.connect(name, client).await.unwrap();
+        stream
+            .write_all(b"GET /games HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        // A peer closing without close_notify is fine once the body is in.
+        let _ = stream.read_to_string(&mut response).await;
+        server_task.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\n[]"));
+    }
+}